@@ -1,58 +1,232 @@
+use std::env;
+use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use colored::Colorize;
-use elf::endian::AnyEndian;
-use elf::ElfBytes;
 use glob::glob;
 
 use librv64emu::errors::*;
+use librv64emu::htif::Htif;
+use librv64emu::loader::load_elf;
+use librv64emu::signature::write_signature;
 use librv64emu::Processor;
 use librv64emu::system_bus::SystemBusMap;
 use librv64emu::system_bus::SystemBus;
 
-fn run_test(path: &PathBuf) -> io::Result<()> {
-    print!("test: {}: ", path.to_str().expect("Bad path"));
+const USAGE: &str = "Usage: riscv-tests [--dir <isa dir>] [--timeout <instructions>] [--jobs <n>] \
+                     [--junit <report.xml>] [--signature-dir <dir>] [<pattern>...]";
 
-    let file_data = std::fs::read(path)?;
-    let slice = file_data.as_slice();
-    let file = ElfBytes::<AnyEndian>::minimal_parse(slice).expect("Bad parse");
-    let segments =  file.segments().expect("Bad program headers");
+const DEFAULT_DIR: &str = "riscv-tests/isa";
+const DEFAULT_PATTERN: &str = "rv64ui-p-*";
+const DEFAULT_TIMEOUT: u64 = 1_000_000;
+
+// Enough for the page tables and user pages of the -v- environment
+const DRAM_SIZE: usize = 0x40_0000;
+
+enum Outcome {
+    Pass,
+    // Number of the failing test case, as held in gp
+    Fail(u64),
+    Timeout,
+    Error(String),
+}
+
+struct TestResult {
+    name: String,
+    outcome: Outcome,
+    time: Duration,
+}
+
+// On halt, the riscv-arch-test signature goes to <signature_dir>/<test>.signature
+fn run_test(path: &Path, mut timeout: u64, signature_dir: Option<&Path>) -> Outcome {
+    let file_data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => return Outcome::Error(err.to_string()),
+    };
 
     let sbus_map = SystemBusMap {
         dram_base_addr: 0x8000_0000,
-        dram_size: 0x1_0000,
+        dram_size: DRAM_SIZE,
     };
     let mut sbus = SystemBus::new(sbus_map);
 
-    for segment in segments.iter().skip(1) {
-        let data = file.segment_data(&segment).expect("Bad parse segment data");
-        sbus.bulk_store_segment(data.to_vec(), segment.p_paddr - 0x8000_0000);
-    }
+    let elf = match load_elf(&mut sbus, &file_data) {
+        Ok(elf) => elf,
+        Err(err) => return Outcome::Error(format!("{:?}", err)),
+    };
+    let htif = match Htif::from_elf(&elf) {
+        Some(htif) => htif,
+        None => return Outcome::Error(String::from("no tohost symbol")),
+    };
     let mut processor = Processor::new(sbus);
-    processor.set_pc(0x8000_0000);
+    processor.set_pc(elf.entry());
+    processor.attach_htif(htif);
 
-    while let Ok(()) = processor.tick() {
-        continue;   
+    let code = loop {
+        if timeout == 0 {
+            return Outcome::Timeout;
+        }
+        timeout -= 1;
+        match processor.tick() {
+            Ok(()) => continue,
+            Err(ProcessorError::Exit(code)) => break code,
+            Err(err) => return Outcome::Error(format!("{:?}", err)),
+        }
+    };
+    if let (Some(dir), Some(name)) = (signature_dir, path.file_name()) {
+        let mut signature = Path::new(dir).join(name).into_os_string();
+        signature.push(".signature");
+        let written = fs::File::create(&signature)
+            .and_then(|mut out| write_signature(processor.system_bus_mut(), &elf, &mut out));
+        if let Err(err) = written {
+            return Outcome::Error(format!("signature: {}", err));
+        }
     }
-    match processor.tick() {
-        Err(ProcessorError::BufferOverflow) => println!("{}", "OK".green()),
-        _ => println!("{}\n{}", "ERROR".red(), processor.dump()),
+    match code {
+        0 => Outcome::Pass,
+        test => Outcome::Fail(test),
+    }
+}
+
+fn report(result: &TestResult) {
+    let status = match &result.outcome {
+        Outcome::Pass => "OK".green(),
+        Outcome::Fail(test) => format!("FAIL (test {})", test).red(),
+        Outcome::Timeout => "TIMEOUT".yellow(),
+        Outcome::Error(err) => format!("ERROR ({})", err).red(),
     };
-    Ok(())
+    println!("test: {}: {}", result.name, status);
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_junit(path: &str, results: &[TestResult]) -> io::Result<()> {
+    let count = |f: fn(&Outcome) -> bool| results.iter().filter(|r| f(&r.outcome)).count();
+    let failures = count(|o| matches!(o, Outcome::Fail(_) | Outcome::Timeout));
+    let errors = count(|o| matches!(o, Outcome::Error(_)));
+    let total = results.iter().fold(0.0, |sum, r| sum + r.time.as_secs_f64());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!("<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+                    results.len(), failures, errors, total);
+    xml += &format!("  <testsuite name=\"riscv-tests\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+                    results.len(), failures, errors, total);
+    for result in results {
+        // rv64ui-p-addi belongs to rv64ui-p
+        let classname = result.name.rsplit_once('-').map_or(result.name.as_str(), |(suite, _)| suite);
+        xml += &format!("    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                        xml_escape(classname), xml_escape(&result.name), result.time.as_secs_f64());
+        match &result.outcome {
+            Outcome::Pass => xml += "/>\n",
+            Outcome::Fail(test) => {
+                xml += &format!(">\n      <failure type=\"fail\" message=\"test {} failed\"/>\n    </testcase>\n", test);
+            }
+            Outcome::Timeout => {
+                xml += ">\n      <failure type=\"timeout\" message=\"instruction limit reached\"/>\n    </testcase>\n";
+            }
+            Outcome::Error(err) => {
+                xml += &format!(">\n      <error message=\"{}\"/>\n    </testcase>\n", xml_escape(err));
+            }
+        }
+    }
+    xml += "  </testsuite>\n</testsuites>\n";
+    fs::write(path, xml)
 }
 
 fn main() -> io::Result<()> {
-    for entry in glob("riscv-tests/isa/rv64ui-p-addi").expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                if path.to_str().unwrap().ends_with(".dump") {
-                    continue;
-                }
-                let _ = run_test(&path);
-            },
-            Err(e) => println!("{:?}", e),
+    let args: Vec<String> = env::args().collect();
+    let mut dir = String::from(DEFAULT_DIR);
+    let mut timeout = DEFAULT_TIMEOUT;
+    let mut jobs = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut junit = None;
+    let mut signature_dir: Option<PathBuf> = None;
+    let mut patterns = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().unwrap_or_else(|| panic!("{}", USAGE));
+        match arg.as_str() {
+            "--dir" => dir = value().clone(),
+            "--timeout" => timeout = value().parse().unwrap_or_else(|_| panic!("{}", USAGE)),
+            "--jobs" => jobs = value().parse().unwrap_or_else(|_| panic!("{}", USAGE)),
+            "--junit" => junit = Some(value().clone()),
+            "--signature-dir" => signature_dir = Some(PathBuf::from(value())),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            pattern => patterns.push(pattern.to_string()),
         }
     }
+    if patterns.is_empty() {
+        patterns.push(String::from(DEFAULT_PATTERN));
+    }
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for pattern in &patterns {
+        let full = format!("{}/{}", dir, pattern);
+        for entry in glob(&full).expect("Failed to read glob pattern") {
+            match entry {
+                Ok(path) => {
+                    if path.extension().is_some_and(|ext| ext == "dump") || paths.contains(&path) {
+                        continue;
+                    }
+                    paths.push(path);
+                },
+                Err(e) => println!("{:?}", e),
+            }
+        }
+    }
+    paths.sort();
+
+    // Workers take the next test off a shared index
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let path = match paths.get(index) {
+                    Some(path) => path,
+                    None => break,
+                };
+                let start = Instant::now();
+                let outcome = run_test(path, timeout, signature_dir.as_deref());
+                let result = TestResult {
+                    name: path.file_name().map_or_else(|| path.display().to_string(),
+                                                       |name| name.to_string_lossy().into_owned()),
+                    outcome,
+                    time: start.elapsed(),
+                };
+                report(&result);
+                results.lock().unwrap().push(result);
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let passed = results.iter().filter(|r| matches!(r.outcome, Outcome::Pass)).count();
+    let timed_out = results.iter().filter(|r| matches!(r.outcome, Outcome::Timeout)).count();
+    let failed = results.len() - passed - timed_out;
+    println!("\n{} tests: {} passed, {} failed, {} timed out",
+             results.len(), passed, failed, timed_out);
+
+    if let Some(path) = junit {
+        write_junit(&path, &results)?;
+    }
+    if passed != results.len() {
+        process::exit(1);
+    }
     Ok(())
 }
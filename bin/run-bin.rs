@@ -1,15 +1,75 @@
 use std::env;
 use std::fs::File;
 use std::io::{self, Read};
+use std::process;
 
+use librv64emu::errors::ProcessorError;
+use librv64emu::htif::Htif;
+use librv64emu::loader::{is_elf, load_elf, load_kernel};
+use librv64emu::signature::write_signature;
 use librv64emu::Processor;
 use librv64emu::system_bus::SystemBusMap;
 use librv64emu::system_bus::SystemBus;
+use librv64emu::uart::{stdin_channel, RawMode, Uart, UART_BASE, UART_IRQ, UART_SIZE};
+use librv64emu::virtio::{VirtioDevice, VirtioMmio, VIRTIO_BASE, VIRTIO_IRQ, VIRTIO_SIZE};
+use librv64emu::virtio::blk::{Disk, DiskMode, VirtioBlk};
+use librv64emu::virtio::console::VirtioConsole;
+use librv64emu::virtio::net::{PcapBackend, VirtioNet, DEFAULT_MAC};
+use librv64emu::virtio::rng::VirtioRng;
+
+const USAGE: &str =
+    "Usage: run-bin <filename> [--disk <image>] [--disk-ro <image>] [--net-pcap <capture>] \
+     [--hvc] [--rng <seed|random>] [--append <bootargs>] [--memory <MiB>] [--signature <file>] \
+     [--sbi]";
+
+const DEFAULT_MEMORY_MIB: usize = 128;
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        panic!("Usage: j4frv32emu <filename>");
+    if args.len() < 2 {
+        panic!("{}", USAGE);
+    }
+    // Disk images are copy-on-write unless asked to be read-only
+    let mut virtio: Vec<Box<dyn VirtioDevice>> = Vec::new();
+    let mut hvc = false;
+    let mut bootargs = None;
+    let mut memory = DEFAULT_MEMORY_MIB;
+    let mut signature = None;
+    let mut sbi = false;
+    let mut rest = args[2..].iter();
+    while let Some(arg) = rest.next() {
+        let mut value = || rest.next().unwrap_or_else(|| panic!("{}", USAGE));
+        match arg.as_str() {
+            "--disk" => virtio.push(Box::new(VirtioBlk::new(Disk::open(value(), DiskMode::CopyOnWrite)?))),
+            "--disk-ro" => virtio.push(Box::new(VirtioBlk::new(Disk::open(value(), DiskMode::ReadOnly)?))),
+            // Frames sent by the guest are captured, nothing is received
+            "--net-pcap" => {
+                let backend = PcapBackend::new(None, Some(Box::new(File::create(value())?)))?;
+                virtio.push(Box::new(VirtioNet::new(DEFAULT_MAC, Box::new(backend))));
+            }
+            // The virtio console takes over stdin from the UART
+            "--hvc" if !hvc => {
+                let mut console = VirtioConsole::new();
+                console.set_input(stdin_channel());
+                virtio.push(Box::new(console));
+                hvc = true;
+            }
+            "--rng" => {
+                let rng = match value().as_str() {
+                    "random" => VirtioRng::from_entropy(),
+                    seed => VirtioRng::new(seed.parse().unwrap_or_else(|_| panic!("{}", USAGE))),
+                };
+                eprintln!("virtio-rng seed: {}", rng.seed());
+                virtio.push(Box::new(rng));
+            }
+            // Boot the file as an S-mode kernel on the built-in SBI
+            "--sbi" => sbi = true,
+            "--append" => bootargs = Some(value().clone()),
+            "--memory" => memory = value().parse().unwrap_or_else(|_| panic!("{}", USAGE)),
+            // riscv-arch-test signature, written when the guest halts through HTIF
+            "--signature" => signature = Some(value().clone()),
+            _ => panic!("{}", USAGE),
+        }
     }
     let mut file = File::open(&args[1])?;
     let mut data = Vec::new();
@@ -17,15 +77,62 @@ fn main() -> io::Result<()> {
 
     let sbus_map = SystemBusMap {
         dram_base_addr: 0x8000_0000,
-        dram_size: 0x1_0000,
+        dram_size: memory << 20,
     };
     let mut sbus = SystemBus::new(sbus_map);
-    sbus.bulk_store(data);
+    // ELF images go where their headers say, raw binaries at the start of RAM
+    let elf = if is_elf(&data) {
+        Some(load_elf(&mut sbus, &data).expect("Bad ELF image"))
+    } else {
+        None
+    };
+    let entry = match &elf {
+        Some(elf) => elf.entry(),
+        // Kernels go where their header or the firmware convention says
+        None if sbi => load_kernel(&mut sbus, &data).expect("Kernel does not fit in RAM"),
+        None => {
+            sbus.write_bytes(sbus.dram_base(), &data).expect("Binary does not fit in RAM");
+            sbus.dram_base()
+        }
+    };
+    let mut uart = Uart::new();
+    if !hvc {
+        uart.attach_stdin();
+    }
+    sbus.register(UART_BASE, UART_SIZE, Box::new(uart), Some(UART_IRQ))
+        .expect("UART range is free");
+    for (slot, device) in virtio.into_iter().enumerate() {
+        let mmio = VirtioMmio::new(device);
+        sbus.register(VIRTIO_BASE + slot as u64 * VIRTIO_SIZE, VIRTIO_SIZE, Box::new(mmio),
+                      Some(VIRTIO_IRQ + slot)).expect("virtio slot is free");
+    }
 
     let mut processor = Processor::new(sbus);
-    while let Ok(()) = processor.tick() {
-        continue;   
+    if sbi {
+        processor.boot_supervisor(entry);
+    } else {
+        processor.set_pc(entry);
+    }
+    if let Some(htif) = elf.as_ref().and_then(Htif::from_elf) {
+        processor.attach_htif(htif);
+    }
+    if let Err(err) = processor.load_dtb(bootargs.as_deref()) {
+        eprintln!("no room for the device tree: {:?}", err);
+    }
+    let raw_mode = RawMode::enable();
+    let res = loop {
+        if let Err(err) = processor.tick() {
+            break err;
+        }
+    };
+    drop(raw_mode);
+    if let ProcessorError::Exit(code) = res {
+        if let (Some(path), Some(elf)) = (&signature, &elf) {
+            let mut out = File::create(path)?;
+            write_signature(processor.system_bus_mut(), elf, &mut out)?;
+        }
+        process::exit(code as i32);
     }
-    println!("{}", processor.dump());
+    println!("{:?}\n{}", res, processor.dump());
     Ok(())
 }
\ No newline at end of file
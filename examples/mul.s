@@ -1,3 +1,3 @@
-muli x29, x0, 5
-muli x30, x0, 37
-mul x31, x30, x29
\ No newline at end of file
+addi x29, x0, 5
+addi x30, x0, 37
+mul x31, x30, x29
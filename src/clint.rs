@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+
+use crate::device::Device;
+use crate::errors::SystemBusError;
+
+pub const CLINT_BASE: u64 = 0x0200_0000;
+pub const CLINT_SIZE: u64 = 0x1_0000;
+
+// Register layout of the SiFive CLINT
+const MSIP_BASE: u64 = 0x0000;
+const MTIMECMP_BASE: u64 = 0x4000;
+const MTIME: u64 = 0xbff8;
+
+pub const CLINT_MAX_HARTS: usize = 4095;
+
+// Nominal mtime rate advertised to the guest, mtime really counts cycles
+pub const CLINT_TIMEBASE_FREQ: u64 = 10_000_000;
+
+/// Core-local interruptor: the machine timer and software interrupts.
+pub struct Clint {
+    msip: Vec<u32>,
+    mtimecmp: Vec<u64>,
+    mtime: u64,
+}
+
+impl Clint {
+    pub fn new() -> Self {
+        Clint {
+            msip: vec![0; CLINT_MAX_HARTS],
+            // Far in the future, so no timer interrupt is pending at reset
+            mtimecmp: vec![u64::MAX; CLINT_MAX_HARTS],
+            mtime: 0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    pub fn msip(&self, hartid: u64) -> bool {
+        self.msip.get(hartid as usize).is_some_and(|msip| msip & 0x1 != 0)
+    }
+
+    pub fn mtip(&self, hartid: u64) -> bool {
+        self.mtimecmp.get(hartid as usize).is_some_and(|&cmp| self.mtime >= cmp)
+    }
+
+    pub fn set_msip(&mut self, hartid: u64, pending: bool) {
+        if let Some(msip) = self.msip.get_mut(hartid as usize) {
+            *msip = pending as u32;
+        }
+    }
+
+    pub fn set_mtimecmp(&mut self, hartid: u64, value: u64) {
+        if let Some(cmp) = self.mtimecmp.get_mut(hartid as usize) {
+            *cmp = value;
+        }
+    }
+
+    // Register containing `offset`, its address and width in bytes
+    fn register(&self, offset: u64) -> Option<(u64, u64, u64)> {
+        let msip_end = MSIP_BASE + 4 * CLINT_MAX_HARTS as u64;
+        let mtimecmp_end = MTIMECMP_BASE + 8 * CLINT_MAX_HARTS as u64;
+        if offset < msip_end {
+            let base = offset & !0x3;
+            Some((base, self.msip[(base / 4) as usize] as u64, 4))
+        } else if (MTIMECMP_BASE..mtimecmp_end).contains(&offset) {
+            let base = offset & !0x7;
+            Some((base, self.mtimecmp[((base - MTIMECMP_BASE) / 8) as usize], 8))
+        } else if (MTIME..MTIME + 8).contains(&offset) {
+            Some((MTIME, self.mtime, 8))
+        } else {
+            None
+        }
+    }
+}
+
+impl Device for Clint {
+    fn load(&mut self, offset: u64, size: usize) -> Result<u64, SystemBusError> {
+        let (base, value, width) = self.register(offset).ok_or(SystemBusError::InvalidAddress)?;
+        if offset - base + size as u64 / 8 > width {
+            return Err(SystemBusError::InvalidAddress);
+        }
+        let value = value >> (8 * (offset - base));
+        Ok(if size == 64 { value } else { value & ((0x1 << size) - 1) })
+    }
+
+    fn store(&mut self, data: u64, offset: u64, size: usize) -> Result<(), SystemBusError> {
+        let (base, old, width) = self.register(offset).ok_or(SystemBusError::InvalidAddress)?;
+        if offset - base + size as u64 / 8 > width {
+            return Err(SystemBusError::InvalidAddress);
+        }
+        let shift = 8 * (offset - base);
+        let mask = if size == 64 { u64::MAX } else { ((0x1 << size) - 1) << shift };
+        let value = (old & !mask) | ((data << shift) & mask);
+        if base < MTIMECMP_BASE {
+            // Only bit 0 of msip is implemented
+            self.msip[(base / 4) as usize] = (value & 0x1) as u32;
+        } else if base == MTIME {
+            self.mtime = value;
+        } else {
+            self.mtimecmp[((base - MTIMECMP_BASE) / 8) as usize] = value;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Clint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,51 +3,61 @@
 
 pub fn rd(inst: u32) -> usize {
     // rd in bits 11..7
-    return ((inst >> 7) & 0x1f) as usize;
+    ((inst >> 7) & 0x1f) as usize
 }
 
 pub fn rs1(inst: u32) -> usize {
     // rs1 in bits 19..15
-    return ((inst >> 15) & 0x1f) as usize;
+    ((inst >> 15) & 0x1f) as usize
 }
 
 pub fn rs2(inst: u32) -> usize {
-    return ((inst >> 20) & 0x1f) as usize;   // rs2 in bits 24..20
+    ((inst >> 20) & 0x1f) as usize   // rs2 in bits 24..20
+}
+
+pub fn rs3(inst: u32) -> usize {
+    // rs3 in bits 31..27
+    ((inst >> 27) & 0x1f) as usize
 }
 
 pub fn imm_I(inst: u32) -> u64 {
     // imm[11:0] = inst[31:20]
-    return ((inst & 0xfff00000) >> 20) as u64; // right shift as signed?
+    ((inst as i32 as i64) >> 20) as u64
 }
 
 pub fn imm_S(inst: u32) -> u64 {
     // imm[11:5] = inst[31:25], imm[4:0] = inst[11:7]
-    return (((inst & 0xfe000000) >> 20)
-         | ((inst >> 7) & 0x1f)) as u64;
+    (((inst & 0xfe000000) as i32 as i64 >> 20) as u64)
+        | ((inst >> 7) & 0x1f) as u64
 }
 
 pub fn imm_B(inst: u32) -> u64 {
     // imm[12|10:5|4:1|11] = inst[31|30:25|11:8|7]
-    return (((inst & 0x80000000) >> 19)
-        | ((inst & 0x80) << 4) // imm[11]
-        | ((inst >> 20) & 0x7e0) // imm[10:5]
-        | ((inst >> 7) & 0x1e)) as u64; // imm[4:1]
+    (((inst & 0x80000000) as i32 as i64 >> 19) as u64)
+        | ((inst & 0x80) << 4) as u64 // imm[11]
+        | ((inst >> 20) & 0x7e0) as u64 // imm[10:5]
+        | ((inst >> 7) & 0x1e) as u64 // imm[4:1]
 }
 
 pub fn imm_U(inst: u32) -> u64 {
     // imm[31:12] = inst[31:12]
-    return (inst & 0xfffff000) as u64;
+    (inst & 0xfffff000) as i32 as i64 as u64
 }
 
 pub fn imm_J(inst: u32) -> u64 {
     // imm[20|10:1|11|19:12] = inst[31|30:21|20|19:12]
-    return (((inst & 0x80000000) >> 11)
-        | (inst & 0xff000) // imm[19:12]
-        | ((inst >> 9) & 0x800) // imm[11]
-        | ((inst >> 20) & 0x7fe)) as u64; // imm[10:1]
+    (((inst & 0x80000000) as i32 as i64 >> 11) as u64)
+        | (inst & 0xff000) as u64 // imm[19:12]
+        | ((inst >> 9) & 0x800) as u64 // imm[11]
+        | ((inst >> 20) & 0x7fe) as u64 // imm[10:1]
 }
 
 pub fn shamt(inst: u32) -> u64 {
+    // RV64 shifts use a 6-bit shamt, the *W variants only 5 bits
+    imm_I(inst) & 0x3f
+}
+
+pub fn shamt_W(inst: u32) -> u64 {
     imm_I(inst) & 0x1f
 }
 
@@ -59,6 +69,18 @@ pub fn funct3(inst: u32) -> u64 {
     x(inst as u64, 12, 3)
 }
 
+pub fn fmt(inst: u32) -> u64 {
+    x(inst as u64, 25, 2)
+}
+
+pub fn funct5(inst: u32) -> u64 {
+    x(inst as u64, 27, 5)
+}
+
+pub fn funct6(inst: u32) -> u64 {
+    x(inst as u64, 26, 6)
+}
+
 pub fn funct7(inst: u32) -> u64 {
     x(inst as u64, 25, 7)
 }
@@ -67,6 +89,126 @@ pub fn op(inst: u32) -> u64 {
     x(inst as u64, 0, 7)
 }
 
+// Compressed instruction fields
+
+pub fn c_rd(inst: u16) -> usize {
+    // rd/rs1 in bits 11..7
+    ((inst >> 7) & 0x1f) as usize
+}
+
+pub fn c_rs2(inst: u16) -> usize {
+    // rs2 in bits 6..2
+    ((inst >> 2) & 0x1f) as usize
+}
+
+pub fn c_rs1_p(inst: u16) -> usize {
+    // rs1'/rd' in bits 9..7, x8..x15
+    (((inst >> 7) & 0x7) + 8) as usize
+}
+
+pub fn c_rs2_p(inst: u16) -> usize {
+    // rs2'/rd' in bits 4..2, x8..x15
+    (((inst >> 2) & 0x7) + 8) as usize
+}
+
+pub fn c_shamt(inst: u16) -> u64 {
+    // shamt[5] = inst[12], shamt[4:0] = inst[6:2]
+    (((inst >> 7) & 0x20) | ((inst >> 2) & 0x1f)) as u64
+}
+
+pub fn imm_CI(inst: u16) -> u64 {
+    // imm[5] = inst[12], imm[4:0] = inst[6:2]
+    let imm = c_shamt(inst);
+    ((imm << 58) as i64 >> 58) as u64
+}
+
+pub fn imm_CI16SP(inst: u16) -> u64 {
+    // nzimm[9] = inst[12], nzimm[4|6|8:7|5] = inst[6:2]
+    let inst = inst as u64;
+    let imm = ((inst >> 3) & 0x200)
+        | ((inst >> 2) & 0x10)
+        | ((inst << 1) & 0x40)
+        | ((inst << 4) & 0x180)
+        | ((inst << 3) & 0x20);
+    ((imm << 54) as i64 >> 54) as u64
+}
+
+pub fn imm_CIW(inst: u16) -> u64 {
+    // nzuimm[5:4|9:6|2|3] = inst[12:5]
+    let inst = inst as u64;
+    ((inst >> 7) & 0x30)
+        | ((inst >> 1) & 0x3c0)
+        | ((inst >> 4) & 0x4)
+        | ((inst >> 2) & 0x8)
+}
+
+pub fn imm_CLW(inst: u16) -> u64 {
+    // uimm[5:3] = inst[12:10], uimm[2|6] = inst[6:5]
+    let inst = inst as u64;
+    ((inst >> 7) & 0x38)
+        | ((inst >> 4) & 0x4)
+        | ((inst << 1) & 0x40)
+}
+
+pub fn imm_CLD(inst: u16) -> u64 {
+    // uimm[5:3] = inst[12:10], uimm[7:6] = inst[6:5]
+    let inst = inst as u64;
+    ((inst >> 7) & 0x38) | ((inst << 1) & 0xc0)
+}
+
+pub fn imm_CLWSP(inst: u16) -> u64 {
+    // uimm[5] = inst[12], uimm[4:2|7:6] = inst[6:2]
+    let inst = inst as u64;
+    ((inst >> 7) & 0x20)
+        | ((inst >> 2) & 0x1c)
+        | ((inst << 4) & 0xc0)
+}
+
+pub fn imm_CLDSP(inst: u16) -> u64 {
+    // uimm[5] = inst[12], uimm[4:3|8:6] = inst[6:2]
+    let inst = inst as u64;
+    ((inst >> 7) & 0x20)
+        | ((inst >> 2) & 0x18)
+        | ((inst << 4) & 0x1c0)
+}
+
+pub fn imm_CSWSP(inst: u16) -> u64 {
+    // uimm[5:2|7:6] = inst[12:7]
+    let inst = inst as u64;
+    ((inst >> 7) & 0x3c) | ((inst >> 1) & 0xc0)
+}
+
+pub fn imm_CSDSP(inst: u16) -> u64 {
+    // uimm[5:3|8:6] = inst[12:7]
+    let inst = inst as u64;
+    ((inst >> 7) & 0x38) | ((inst >> 1) & 0x1c0)
+}
+
+pub fn imm_CJ(inst: u16) -> u64 {
+    // imm[11|4|9:8|10|6|7|3:1|5] = inst[12:2]
+    let inst = inst as u64;
+    let imm = ((inst >> 1) & 0x800)
+        | ((inst >> 7) & 0x10)
+        | ((inst >> 1) & 0x300)
+        | ((inst << 2) & 0x400)
+        | ((inst >> 1) & 0x40)
+        | ((inst << 1) & 0x80)
+        | ((inst >> 2) & 0xe)
+        | ((inst << 3) & 0x20);
+    ((imm << 52) as i64 >> 52) as u64
+}
+
+pub fn imm_CB(inst: u16) -> u64 {
+    // imm[8|4:3] = inst[12:10], imm[7:6|2:1|5] = inst[6:2]
+    let inst = inst as u64;
+    let imm = ((inst >> 4) & 0x100)
+        | ((inst >> 7) & 0x18)
+        | ((inst << 1) & 0xc0)
+        | ((inst >> 2) & 0x6)
+        | ((inst << 3) & 0x20);
+    ((imm << 55) as i64 >> 55) as u64
+}
+
 fn x(inst: u64, lo: usize, len: usize) -> u64 {
     (inst >> lo) & ((1u64 << len) - 1) 
 }
@@ -0,0 +1,72 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use crate::dram::Dram;
+use crate::errors::SystemBusError;
+use crate::fdt::DtNode;
+
+/// A memory-mapped peripheral. Offsets are relative to the base address
+/// the device is registered at, sizes are in bits as for `SystemBus`.
+pub trait Device {
+    fn load(&mut self, offset: u64, size: usize) -> Result<u64, SystemBusError>;
+
+    fn store(&mut self, data: u64, offset: u64, size: usize) -> Result<(), SystemBusError>;
+
+    /// Called once per processor cycle, with guest RAM for DMA.
+    fn tick(&mut self, dma: &mut Dma) {}
+
+    /// Level of the interrupt line, routed to the PLIC source the device
+    /// was registered with.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Node in the generated device tree, `None` to leave the device out.
+    fn dt_node(&self) -> Option<DtNode> {
+        None
+    }
+}
+
+/// Guest RAM addressed physically, as seen by devices doing DMA.
+pub struct Dma<'a> {
+    base: u64,
+    dram: &'a mut Dram,
+}
+
+impl<'a> Dma<'a> {
+    pub fn new(base: u64, dram: &'a mut Dram) -> Self {
+        Dma { base, dram }
+    }
+
+    // DRAM offset of [addr, addr + len), if all of it is RAM
+    fn offset(&self, addr: u64, len: usize) -> Result<u64, SystemBusError> {
+        let offset = addr.checked_sub(self.base).ok_or(SystemBusError::InvalidAddress)?;
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.dram.size() as u64 => Ok(offset),
+            _ => Err(SystemBusError::InvalidAddress),
+        }
+    }
+
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), SystemBusError> {
+        let offset = self.offset(addr, buf.len())?;
+        self.dram.read_bytes(offset, buf);
+        Ok(())
+    }
+
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), SystemBusError> {
+        let offset = self.offset(addr, data.len())?;
+        self.dram.write_bytes(offset, data);
+        Ok(())
+    }
+
+    pub fn load(&self, addr: u64, size: usize) -> Result<u64, SystemBusError> {
+        let offset = self.offset(addr, size / 8)?;
+        Ok(self.dram.load(offset, size))
+    }
+
+    pub fn store(&mut self, data: u64, addr: u64, size: usize) -> Result<(), SystemBusError> {
+        let offset = self.offset(addr, size / 8)?;
+        self.dram.store(data, offset, size);
+        Ok(())
+    }
+}
@@ -1,12 +1,36 @@
+use crate::trap::Exception;
+
 #[derive(Debug)]
 pub enum ProcessorError {
     NotYetImplemented,
     FetchError,
-    BufferOverflow,
+    // The guest asked through HTIF to stop with this exit code
+    Exit(u64),
+    // The handler of this exception cannot be fetched
+    DoubleFault(Exception),
 }
 
 #[derive(Debug)]
 pub enum SystemBusError {
     InvalidAddress,
     NotYetImplemented,
+    // The device range collides with one already mapped
+    Overlap,
+}
+
+#[derive(Debug)]
+pub enum LoaderError {
+    Parse(elf::ParseError),
+    // Only 64-bit images run on this hart
+    WrongClass,
+    // e_machine of an image that is not for RISC-V
+    WrongMachine(u16),
+    // Address of a segment that does not fit in RAM
+    SegmentOutOfRange(u64),
+}
+
+impl From<elf::ParseError> for LoaderError {
+    fn from(err: elf::ParseError) -> Self {
+        LoaderError::Parse(err)
+    }
 }
@@ -0,0 +1,364 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use crate::clint::{CLINT_BASE, CLINT_SIZE, CLINT_TIMEBASE_FREQ};
+use crate::opcodes::*;
+use crate::plic::{PLIC_BASE, PLIC_SIZE, PLIC_SOURCES};
+use crate::system_bus::SystemBus;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+// Interrupt numbers seen by the CPU interrupt controller
+const IRQ_S_SOFT: u32 = 1;
+const IRQ_M_SOFT: u32 = 3;
+const IRQ_S_TIMER: u32 = 5;
+const IRQ_M_TIMER: u32 = 7;
+const IRQ_S_EXT: u32 = 9;
+const IRQ_M_EXT: u32 = 11;
+
+/// How a peripheral shows up under `/soc`: node name, `compatible` and
+/// any extra single-cell properties. `reg` and the interrupt wiring are
+/// filled in from where the device is registered.
+pub struct DtNode {
+    pub name: &'static str,
+    pub compatible: &'static str,
+    pub props: Vec<(&'static str, u32)>,
+}
+
+/// Flattened device tree writer. Nodes and properties are emitted in
+/// order, `finish()` lays out the blob.
+pub struct Fdt {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    string_offsets: HashMap<String, u32>,
+    depth: usize,
+}
+
+impl Fdt {
+    pub fn new() -> Self {
+        Fdt {
+            structure: Vec::new(),
+            strings: Vec::new(),
+            string_offsets: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    fn token(&mut self, token: u32) {
+        self.structure.extend(token.to_be_bytes());
+    }
+
+    fn pad(&mut self) {
+        while self.structure.len() & 0x3 != 0 {
+            self.structure.push(0);
+        }
+    }
+
+    fn string_offset(&mut self, name: &str) -> u32 {
+        if let Some(&offset) = self.string_offsets.get(name) {
+            return offset;
+        }
+        let offset = self.strings.len() as u32;
+        self.strings.extend(name.as_bytes());
+        self.strings.push(0);
+        self.string_offsets.insert(name.to_string(), offset);
+        offset
+    }
+
+    pub fn begin_node(&mut self, name: &str) {
+        self.token(FDT_BEGIN_NODE);
+        self.structure.extend(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+        self.depth += 1;
+    }
+
+    pub fn end_node(&mut self) {
+        self.token(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    pub fn prop(&mut self, name: &str, value: &[u8]) {
+        let offset = self.string_offset(name);
+        self.token(FDT_PROP);
+        self.structure.extend((value.len() as u32).to_be_bytes());
+        self.structure.extend(offset.to_be_bytes());
+        self.structure.extend(value);
+        self.pad();
+    }
+
+    pub fn prop_empty(&mut self, name: &str) {
+        self.prop(name, &[]);
+    }
+
+    pub fn prop_u32(&mut self, name: &str, value: u32) {
+        self.prop(name, &value.to_be_bytes());
+    }
+
+    pub fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.prop(name, &value);
+    }
+
+    /// `reg` with two address and two size cells.
+    pub fn prop_reg(&mut self, base: u64, size: u64) {
+        self.prop_cells("reg", &[(base >> 32) as u32, base as u32, (size >> 32) as u32, size as u32]);
+    }
+
+    pub fn prop_str(&mut self, name: &str, value: &str) {
+        self.prop_strs(name, &[value]);
+    }
+
+    pub fn prop_strs(&mut self, name: &str, values: &[&str]) {
+        let mut value = Vec::new();
+        for s in values {
+            value.extend(s.as_bytes());
+            value.push(0);
+        }
+        self.prop(name, &value);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "unbalanced device tree nodes");
+        self.token(FDT_END);
+        // Header, then an empty memory reservation map, structure and strings
+        let off_mem_rsvmap = FDT_HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + 16;
+        let off_dt_strings = off_dt_struct + self.structure.len();
+        let totalsize = off_dt_strings + self.strings.len();
+        let header = [
+            FDT_MAGIC,
+            totalsize as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+        let mut blob: Vec<u8> = header.iter().flat_map(|word| word.to_be_bytes()).collect();
+        blob.extend([0; 16]);
+        blob.extend(self.structure);
+        blob.extend(self.strings);
+        blob
+    }
+}
+
+impl Default for Fdt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ISA string for the `riscv,isa` property, from the value of misa.
+pub fn isa_string(misa: u64) -> String {
+    let mut isa = match misa >> 62 {
+        1 => String::from("rv32"),
+        _ => String::from("rv64"),
+    };
+    // Canonical order, S and U are privilege modes rather than extensions
+    for ext in "imafdqcbv".chars() {
+        if misa & (0x1 << (ext as u64 - 'a' as u64)) != 0 {
+            isa.push(ext);
+        }
+    }
+    isa
+}
+
+/// Device tree of the machine behind `bus`, with `harts` harts
+/// implementing `misa`.
+pub fn generate(bus: &SystemBus, harts: u32, misa: u64, bootargs: Option<&str>) -> Vec<u8> {
+    // Phandles: one interrupt controller per hart, then the PLIC
+    let intc = |hart: u32| hart + 1;
+    let plic_phandle = harts + 1;
+    let isa = isa_string(misa);
+
+    let mut fdt = Fdt::new();
+    fdt.begin_node("");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "riscv-virtio");
+    fdt.prop_str("model", "j4frv32emu");
+
+    let serial = bus.devices()
+        .find(|(_, _, _, device)| device.dt_node().is_some_and(|node| node.name == "serial"))
+        .map(|(base, ..)| base);
+    fdt.begin_node("chosen");
+    if let Some(bootargs) = bootargs {
+        fdt.prop_str("bootargs", bootargs);
+    }
+    if let Some(base) = serial {
+        fdt.prop_str("stdout-path", &format!("/soc/serial@{:x}", base));
+    }
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{:x}", bus.dram_base()));
+    fdt.prop_str("device_type", "memory");
+    fdt.prop_reg(bus.dram_base(), bus.dram_size() as u64);
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.prop_u32("#address-cells", 1);
+    fdt.prop_u32("#size-cells", 0);
+    fdt.prop_u32("timebase-frequency", CLINT_TIMEBASE_FREQ as u32);
+    for hart in 0..harts {
+        fdt.begin_node(&format!("cpu@{:x}", hart));
+        fdt.prop_str("device_type", "cpu");
+        fdt.prop_u32("reg", hart);
+        fdt.prop_str("status", "okay");
+        fdt.prop_str("compatible", "riscv");
+        fdt.prop_str("riscv,isa", &isa);
+        if misa & MISA_S != 0 {
+            fdt.prop_str("mmu-type", "riscv,sv57");
+        }
+        fdt.begin_node("interrupt-controller");
+        fdt.prop_u32("#interrupt-cells", 1);
+        fdt.prop_empty("interrupt-controller");
+        fdt.prop_str("compatible", "riscv,cpu-intc");
+        fdt.prop_u32("phandle", intc(hart));
+        fdt.end_node();
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.prop_u32("#address-cells", 2);
+    fdt.prop_u32("#size-cells", 2);
+    fdt.prop_str("compatible", "simple-bus");
+    fdt.prop_empty("ranges");
+
+    fdt.begin_node(&format!("clint@{:x}", CLINT_BASE));
+    fdt.prop_strs("compatible", &["sifive,clint0", "riscv,clint0"]);
+    fdt.prop_reg(CLINT_BASE, CLINT_SIZE);
+    let cells: Vec<u32> = (0..harts)
+        .flat_map(|hart| [intc(hart), IRQ_M_SOFT, intc(hart), IRQ_M_TIMER])
+        .collect();
+    fdt.prop_cells("interrupts-extended", &cells);
+    fdt.end_node();
+
+    fdt.begin_node(&format!("plic@{:x}", PLIC_BASE));
+    fdt.prop_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+    fdt.prop_reg(PLIC_BASE, PLIC_SIZE);
+    fdt.prop_u32("#address-cells", 0);
+    fdt.prop_u32("#interrupt-cells", 1);
+    fdt.prop_empty("interrupt-controller");
+    fdt.prop_u32("riscv,ndev", PLIC_SOURCES as u32 - 1);
+    // Contexts go M, S for each hart
+    let cells: Vec<u32> = (0..harts)
+        .flat_map(|hart| [intc(hart), IRQ_M_EXT, intc(hart), IRQ_S_EXT])
+        .collect();
+    fdt.prop_cells("interrupts-extended", &cells);
+    fdt.prop_u32("phandle", plic_phandle);
+    fdt.end_node();
+
+    for (base, size, irq, device) in bus.devices() {
+        let node = match device.dt_node() {
+            Some(node) => node,
+            None => continue,
+        };
+        fdt.begin_node(&format!("{}@{:x}", node.name, base));
+        fdt.prop_str("compatible", node.compatible);
+        fdt.prop_reg(base, size);
+        if let Some(irq) = irq {
+            fdt.prop_u32("interrupts", irq as u32);
+            fdt.prop_u32("interrupt-parent", plic_phandle);
+        }
+        for (name, value) in node.props {
+            fdt.prop_u32(name, value);
+        }
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::system_bus::SystemBusMap;
+    use crate::uart::{Uart, UART_BASE, UART_IRQ, UART_SIZE};
+
+    use super::*;
+
+    fn be32(blob: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn cstr(blob: &[u8], offset: usize) -> &str {
+        let len = blob[offset..].iter().position(|&b| b == 0).unwrap();
+        std::str::from_utf8(&blob[offset..offset + len]).unwrap()
+    }
+
+    // Every property as ("/path/to/node:name", value)
+    fn walk(blob: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let strings = be32(blob, 12) as usize;
+        let mut offset = be32(blob, 8) as usize;
+        let mut path: Vec<String> = Vec::new();
+        let mut props = Vec::new();
+        loop {
+            let token = be32(blob, offset);
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(blob, offset).to_string();
+                    offset += (name.len() + 4) & !0x3;
+                    path.push(name);
+                }
+                FDT_END_NODE => {
+                    path.pop();
+                }
+                FDT_PROP => {
+                    let len = be32(blob, offset) as usize;
+                    let name = cstr(blob, strings + be32(blob, offset + 4) as usize);
+                    let value = blob[offset + 8..offset + 8 + len].to_vec();
+                    props.push((format!("{}:{}", path.join("/"), name), value));
+                    offset += (8 + len + 3) & !0x3;
+                }
+                FDT_END => break,
+                _ => panic!("bad token {:#x}", token),
+            }
+        }
+        assert!(path.is_empty());
+        props
+    }
+
+    fn find<'a>(props: &'a [(String, Vec<u8>)], key: &str) -> &'a [u8] {
+        &props.iter().find(|(k, _)| k == key).unwrap_or_else(|| panic!("no {}", key)).1
+    }
+
+    #[test]
+    fn fdt_generate_test() {
+        let mut bus = SystemBus::new(SystemBusMap {
+            dram_base_addr: 0x8000_0000,
+            dram_size: 0x10_0000,
+        });
+        bus.register(UART_BASE, UART_SIZE, Box::new(Uart::new()), Some(UART_IRQ)).unwrap();
+        let misa = (0x2 << 62) | MISA_S | 0x1 << 8 | 0x1 << 12 | 0x1 << 2;
+        let blob = generate(&bus, 1, misa, Some("console=ttyS0"));
+
+        assert_eq!(be32(&blob, 0), FDT_MAGIC);
+        assert_eq!(be32(&blob, 4) as usize, blob.len());
+        let props = walk(&blob);
+        assert_eq!(find(&props, "/chosen:bootargs"), b"console=ttyS0\0");
+        assert_eq!(find(&props, "/chosen:stdout-path"), b"/soc/serial@10000000\0");
+        assert_eq!(find(&props, "/memory@80000000:reg"), &[0, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0x10, 0, 0]);
+        assert_eq!(find(&props, "/cpus/cpu@0:riscv,isa"), b"rv64imc\0");
+        assert_eq!(find(&props, "/soc/serial@10000000:interrupts"), &[0, 0, 0, 10]);
+        assert_eq!(find(&props, "/soc/serial@10000000:interrupt-parent"), &[0, 0, 0, 2]);
+        assert_eq!(find(&props, "/soc/plic@c000000:phandle"), &[0, 0, 0, 2]);
+        assert_eq!(find(&props, "/soc/clint@2000000:interrupts-extended"),
+                   &[0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 7]);
+    }
+}
@@ -0,0 +1,293 @@
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+
+use rustc_apfloat::ieee::{Double, Single};
+use rustc_apfloat::{Category, Float, Round, Status, StatusAnd};
+
+// fflags bits
+pub const FFLAGS_NX: u64 = 0x01;
+pub const FFLAGS_UF: u64 = 0x02;
+pub const FFLAGS_OF: u64 = 0x04;
+pub const FFLAGS_DZ: u64 = 0x08;
+pub const FFLAGS_NV: u64 = 0x10;
+
+// rounding modes
+pub const RM_RNE: u64 = 0x0;
+pub const RM_RTZ: u64 = 0x1;
+pub const RM_RDN: u64 = 0x2;
+pub const RM_RUP: u64 = 0x3;
+pub const RM_RMM: u64 = 0x4;
+pub const RM_DYN: u64 = 0x7;
+
+/// An IEEE format that can live in the 64-bit `f` registers.
+pub trait FpFormat: Float {
+    const CANONICAL_NAN: u64;
+
+    /// Reads a value from a register, checking its NaN-boxing.
+    fn unbox(reg: u64) -> Self;
+
+    /// Raw bits of a register value, as seen by sign injection.
+    fn unbox_bits(reg: u64) -> u64;
+
+    /// Widens raw bits to a register value.
+    fn box_bits(bits: u64) -> u64;
+
+    fn sign_bit() -> u64 {
+        1 << (Self::BITS - 1)
+    }
+}
+
+impl FpFormat for Single {
+    const CANONICAL_NAN: u64 = 0x7fc0_0000;
+
+    fn unbox(reg: u64) -> Self {
+        Single::from_bits(Self::unbox_bits(reg) as u128)
+    }
+
+    fn unbox_bits(reg: u64) -> u64 {
+        // Improperly boxed values read as the canonical NaN
+        if reg >> 32 == 0xffff_ffff {
+            reg & 0xffff_ffff
+        } else {
+            Self::CANONICAL_NAN
+        }
+    }
+
+    fn box_bits(bits: u64) -> u64 {
+        0xffff_ffff_0000_0000 | (bits & 0xffff_ffff)
+    }
+}
+
+impl FpFormat for Double {
+    const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+
+    fn unbox(reg: u64) -> Self {
+        Double::from_bits(reg as u128)
+    }
+
+    fn unbox_bits(reg: u64) -> u64 {
+        reg
+    }
+
+    fn box_bits(bits: u64) -> u64 {
+        bits
+    }
+}
+
+pub fn rounding_mode(rm: u64) -> Option<Round> {
+    match rm {
+        RM_RNE => Some(Round::NearestTiesToEven),
+        RM_RTZ => Some(Round::TowardZero),
+        RM_RDN => Some(Round::TowardNegative),
+        RM_RUP => Some(Round::TowardPositive),
+        RM_RMM => Some(Round::NearestTiesToAway),
+        _ => None,
+    }
+}
+
+pub fn fflags(status: Status) -> u64 {
+    let mut flags = 0;
+    if status.contains(Status::INEXACT) {
+        flags |= FFLAGS_NX;
+    }
+    if status.contains(Status::UNDERFLOW) {
+        flags |= FFLAGS_UF;
+    }
+    if status.contains(Status::OVERFLOW) {
+        flags |= FFLAGS_OF;
+    }
+    if status.contains(Status::DIV_BY_ZERO) {
+        flags |= FFLAGS_DZ;
+    }
+    if status.contains(Status::INVALID_OP) {
+        flags |= FFLAGS_NV;
+    }
+    flags
+}
+
+/// Register image of an arithmetic result: NaNs are never propagated,
+/// they always become the canonical NaN.
+pub fn canonical<F: FpFormat>(value: F) -> u64 {
+    if value.is_nan() {
+        F::box_bits(F::CANONICAL_NAN)
+    } else {
+        F::box_bits(value.to_bits() as u64)
+    }
+}
+
+fn signaling<F: FpFormat>(a: F, b: F) -> Status {
+    if a.is_signaling() || b.is_signaling() {
+        Status::INVALID_OP
+    } else {
+        Status::OK
+    }
+}
+
+pub fn sqrt<F: FpFormat>(a: F, round: Round) -> StatusAnd<F> {
+    match a.category() {
+        Category::NaN => return signaling(a, a).and(a),
+        Category::Zero => return Status::OK.and(a),
+        _ if a.is_negative() => return Status::INVALID_OP.and(F::NAN),
+        Category::Infinity => return Status::OK.and(a),
+        Category::Normal => {}
+    }
+
+    // a = sig * 2^exp, with sig an integer
+    let bits = a.to_bits() as u64;
+    let man_bits = F::PRECISION - 1;
+    let bias = F::MAX_EXP as i64;
+    let exp_field = ((bits >> man_bits) & ((1 << (F::BITS - F::PRECISION)) - 1)) as i64;
+    let man = bits & ((1 << man_bits) - 1);
+    let (mut sig, mut exp) = if exp_field == 0 {
+        (man as u128, 1 - bias - man_bits as i64)
+    } else {
+        ((man | (1 << man_bits)) as u128, exp_field - bias - man_bits as i64)
+    };
+    if exp & 1 != 0 {
+        sig <<= 1;
+        exp -= 1;
+    }
+
+    // Scale so the integer root carries a few guard bits past the
+    // precision, then fold the remainder into a sticky bit
+    let sig_width = 128 - sig.leading_zeros() as usize;
+    let mut shift = (2 * (F::PRECISION + 3)).saturating_sub(sig_width);
+    shift += shift & 1;
+    let scaled = sig << shift;
+    let mut root = scaled.isqrt();
+    if root * root != scaled {
+        root |= 1;
+    }
+
+    let StatusAnd { status, value } = F::from_u128_r(root, round);
+    status.and(value.scalbn((exp - shift as i64) as i32 / 2))
+}
+
+pub fn min_max<F: FpFormat>(a: F, b: F, is_max: bool) -> StatusAnd<F> {
+    let status = signaling(a, b);
+    let value = match (a.is_nan(), b.is_nan()) {
+        (true, true) => F::NAN,
+        (true, false) => b,
+        (false, true) => a,
+        // -0.0 is considered to be less than +0.0
+        _ if a.is_zero() && b.is_zero() => {
+            if a.is_negative() != is_max { a } else { b }
+        }
+        _ => match (a.partial_cmp(&b), is_max) {
+            (Some(Ordering::Less), false) | (Some(Ordering::Greater), true) => a,
+            _ => b,
+        },
+    };
+    status.and(value)
+}
+
+pub fn feq<F: FpFormat>(a: F, b: F) -> StatusAnd<bool> {
+    signaling(a, b).and(a.partial_cmp(&b) == Some(Ordering::Equal))
+}
+
+pub fn flt<F: FpFormat>(a: F, b: F) -> StatusAnd<bool> {
+    if a.is_nan() || b.is_nan() {
+        return Status::INVALID_OP.and(false);
+    }
+    Status::OK.and(a < b)
+}
+
+pub fn fle<F: FpFormat>(a: F, b: F) -> StatusAnd<bool> {
+    if a.is_nan() || b.is_nan() {
+        return Status::INVALID_OP.and(false);
+    }
+    Status::OK.and(a <= b)
+}
+
+pub fn classify<F: FpFormat>(a: F) -> u64 {
+    let neg = a.is_negative();
+    let bit = match a.category() {
+        Category::Infinity => if neg { 0 } else { 7 },
+        Category::Normal if a.is_denormal() => if neg { 2 } else { 5 },
+        Category::Normal => if neg { 1 } else { 6 },
+        Category::Zero => if neg { 3 } else { 4 },
+        Category::NaN => if a.is_signaling() { 8 } else { 9 },
+    };
+    1 << bit
+}
+
+/// Float to integer conversion with the RISC-V saturation rules, the
+/// result is sign-extended from `width` bits.
+pub fn to_int<F: FpFormat>(a: F, width: usize, signed: bool, round: Round) -> StatusAnd<u64> {
+    let mut is_exact = false;
+    if signed {
+        if a.is_nan() {
+            return Status::INVALID_OP.and((u64::MAX >> (65 - width)) as i64 as u64);
+        }
+        a.to_i128_r(width, round, &mut is_exact).map(|v| v as u64)
+    } else {
+        if a.is_nan() {
+            return Status::INVALID_OP.and(u64::MAX);
+        }
+        a.to_u128_r(width, round, &mut is_exact)
+            .map(|v| if width == 32 { v as u32 as i32 as u64 } else { v as u64 })
+    }
+}
+
+pub fn from_int<F: FpFormat>(value: u64, width: usize, signed: bool, round: Round) -> StatusAnd<F> {
+    match (signed, width) {
+        (true, 32) => F::from_i128_r(value as i32 as i128, round),
+        (true, _) => F::from_i128_r(value as i64 as i128, round),
+        (false, 32) => F::from_u128_r(value as u32 as u128, round),
+        (false, _) => F::from_u128_r(value as u128, round),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(bits: u32) -> Single {
+        Single::from_bits(bits as u128)
+    }
+
+    #[test]
+    fn sqrt_test() {
+        let rne = Round::NearestTiesToEven;
+        let r = sqrt(s(0x4080_0000), rne); // 4.0
+        assert_eq!(r.value.to_bits(), 0x4000_0000);
+        assert_eq!(r.status, Status::OK);
+
+        let r = sqrt(s(0x4000_0000), rne); // 2.0
+        assert_eq!(r.value.to_bits(), 0x3fb5_04f3);
+        assert_eq!(r.status, Status::INEXACT);
+
+        let r = sqrt(s(0x0000_0001), rne); // smallest subnormal
+        assert_eq!(r.value.to_bits(), 0x1a35_04f3);
+
+        let r = sqrt(s(0xbf80_0000), rne); // -1.0
+        assert!(r.value.is_nan());
+        assert_eq!(r.status, Status::INVALID_OP);
+    }
+
+    #[test]
+    fn to_int_saturation_test() {
+        let rtz = Round::TowardZero;
+        let r = to_int(Single::NAN, 32, true, rtz);
+        assert_eq!((r.value, r.status), (0x7fff_ffff, Status::INVALID_OP));
+        let r = to_int(s(0xff80_0000), 32, true, rtz); // -inf
+        assert_eq!((r.value, r.status), (0xffff_ffff_8000_0000, Status::INVALID_OP));
+        let r = to_int(Single::NAN, 32, false, rtz);
+        assert_eq!((r.value, r.status), (u64::MAX, Status::INVALID_OP));
+        let r = to_int(s(0xbf00_0000), 32, false, rtz); // -0.5
+        assert_eq!((r.value, r.status), (0, Status::INEXACT));
+    }
+
+    #[test]
+    fn double_sqrt_test() {
+        let rne = Round::NearestTiesToEven;
+        let r = sqrt(Double::from_bits(0x4000_0000_0000_0000), rne); // 2.0
+        assert_eq!(r.value.to_bits(), 0x3ff6_a09e_667f_3bcd);
+        assert_eq!(r.status, Status::INEXACT);
+
+        let r = sqrt(Double::from_bits(0x4022_0000_0000_0000), Round::TowardZero); // 9.0
+        assert_eq!(r.value.to_bits(), 0x4008_0000_0000_0000);
+        assert_eq!(r.status, Status::OK);
+    }
+}
@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use std::io::{self, Write};
+
+use crate::loader::ElfImage;
+use crate::system_bus::SystemBus;
+
+// tohost/fromhost layout: device in bits 63:56, command in 55:48, payload below
+const DEVICE_SHIFT: u64 = 56;
+const COMMAND_SHIFT: u64 = 48;
+const PAYLOAD_MASK: u64 = (0x1 << 48) - 1;
+
+const DEVICE_SYSCALL: u64 = 0;
+const DEVICE_CONSOLE: u64 = 1;
+
+const CONSOLE_GETCHAR: u64 = 0;
+const CONSOLE_PUTCHAR: u64 = 1;
+
+// Proxied syscalls, numbered as in the RISC-V Linux ABI
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const ENOSYS: i64 = 38;
+const EBADF: i64 = 9;
+
+/// Berkeley host-target interface as used by riscv-tests and riscv-pk:
+/// the guest posts commands in `tohost` and reads replies in `fromhost`.
+pub struct Htif {
+    tohost: u64,
+    fromhost: Option<u64>,
+    output: Box<dyn Write + Send>,
+}
+
+impl Htif {
+    pub fn new(tohost: u64, fromhost: Option<u64>) -> Self {
+        Htif {
+            tohost,
+            fromhost,
+            output: Box::new(io::stdout()),
+        }
+    }
+
+    /// Uses the `tohost` and `fromhost` symbols of `image`, if it has them.
+    pub fn from_elf(image: &ElfImage) -> Option<Self> {
+        Some(Htif::new(image.symbol("tohost")?, image.symbol("fromhost")))
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+        self.output = output;
+    }
+
+    /// Services the command pending in `tohost`, if any. Returns the
+    /// exit code once the guest asks to stop.
+    pub fn poll(&mut self, bus: &mut SystemBus) -> Option<u64> {
+        let command = bus.load(self.tohost, 64).ok()?;
+        if command == 0 {
+            return None;
+        }
+        // Taking the command frees tohost for the next one
+        let _ = bus.store(0, self.tohost, 64);
+        let device = command >> DEVICE_SHIFT;
+        let cmd = (command >> COMMAND_SHIFT) & 0xff;
+        let payload = command & PAYLOAD_MASK;
+        match (device, cmd) {
+            // Odd payloads are exit codes, even ones point to a syscall block
+            (DEVICE_SYSCALL, 0) if payload & 0x1 != 0 => return Some(payload >> 1),
+            (DEVICE_SYSCALL, 0) => {
+                if let Some(code) = self.syscall(bus, payload) {
+                    return Some(code);
+                }
+                self.respond(bus, device, cmd, 1);
+            }
+            (DEVICE_CONSOLE, CONSOLE_PUTCHAR) => {
+                let _ = self.output.write_all(&[payload as u8]).and_then(|_| self.output.flush());
+            }
+            // No input is wired up, so reads never complete
+            (DEVICE_CONSOLE, CONSOLE_GETCHAR) => {}
+            _ => {}
+        }
+        None
+    }
+
+    fn respond(&mut self, bus: &mut SystemBus, device: u64, cmd: u64, payload: u64) {
+        if let Some(fromhost) = self.fromhost {
+            let reply = (device << DEVICE_SHIFT) | (cmd << COMMAND_SHIFT) | (payload & PAYLOAD_MASK);
+            let _ = bus.store(reply, fromhost, 64);
+        }
+    }
+
+    // Runs the syscall described by the eight words at `magic_mem` and
+    // stores its result in the first one
+    fn syscall(&mut self, bus: &mut SystemBus, magic_mem: u64) -> Option<u64> {
+        let mut args = [0u64; 8];
+        for (i, arg) in args.iter_mut().enumerate() {
+            *arg = bus.load(magic_mem + 8 * i as u64, 64).unwrap_or(0);
+        }
+        let ret = match args[0] {
+            SYS_EXIT | SYS_EXIT_GROUP => return Some(args[1]),
+            SYS_WRITE => self.write(bus, args[1], args[2], args[3]),
+            _ => -ENOSYS,
+        };
+        let _ = bus.store(ret as u64, magic_mem, 64);
+        None
+    }
+
+    fn write(&mut self, bus: &mut SystemBus, fd: u64, buf: u64, len: u64) -> i64 {
+        if fd != 1 && fd != 2 {
+            return -EBADF;
+        }
+        // The guest picks len, so never read past the end of DRAM
+        let dram_end = bus.dram_base() + bus.dram_size() as u64;
+        let len = if (bus.dram_base()..dram_end).contains(&buf) { len.min(dram_end - buf) } else { 0 };
+        let mut data = Vec::new();
+        for addr in buf..buf + len {
+            match bus.load(addr, 8) {
+                Ok(byte) => data.push(byte as u8),
+                Err(_) => break,
+            }
+        }
+        let _ = self.output.write_all(&data).and_then(|_| self.output.flush());
+        data.len() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::system_bus::SystemBusMap;
+    use crate::test_util::Capture;
+
+    use super::*;
+
+    const TOHOST: u64 = 0x8000_1000;
+    const FROMHOST: u64 = 0x8000_1040;
+
+    #[test]
+    fn htif_test() {
+        let mut bus = SystemBus::new(SystemBusMap { dram_base_addr: 0x8000_0000, dram_size: 0x1_0000 });
+        let output = Capture::default();
+        let mut htif = Htif::new(TOHOST, Some(FROMHOST));
+        htif.set_output(Box::new(output.clone()));
+        assert_eq!(htif.poll(&mut bus), None);
+
+        // Console putchar
+        bus.store((DEVICE_CONSOLE << DEVICE_SHIFT) | (CONSOLE_PUTCHAR << COMMAND_SHIFT) | b'x' as u64, TOHOST, 64).unwrap();
+        assert_eq!(htif.poll(&mut bus), None);
+        assert_eq!(bus.load(TOHOST, 64).unwrap(), 0);
+
+        // write(1, "ok", 2) through the syscall proxy
+        let magic = 0x8000_2000;
+        bus.store(SYS_WRITE, magic, 64).unwrap();
+        bus.store(1, magic + 8, 64).unwrap();
+        bus.store(0x8000_3000, magic + 16, 64).unwrap();
+        bus.store(2, magic + 24, 64).unwrap();
+        bus.store(0x6b6f, 0x8000_3000, 16).unwrap();
+        bus.store(magic, TOHOST, 64).unwrap();
+        assert_eq!(htif.poll(&mut bus), None);
+        assert_eq!(bus.load(magic, 64).unwrap(), 2);
+        assert_eq!(bus.load(FROMHOST, 64).unwrap(), 1);
+        assert_eq!(output.contents(), b"xok");
+
+        // A length of -1 stops at the end of DRAM
+        bus.store(SYS_WRITE, magic, 64).unwrap();
+        bus.store(u64::MAX, magic + 24, 64).unwrap();
+        bus.store(0x8000_fffe, magic + 16, 64).unwrap();
+        bus.store(0x2121, 0x8000_fffe, 16).unwrap();
+        bus.store(magic, TOHOST, 64).unwrap();
+        assert_eq!(htif.poll(&mut bus), None);
+        assert_eq!(bus.load(magic, 64).unwrap(), 2);
+        assert_eq!(output.contents(), b"xok!!");
+
+        // riscv-tests report failure of test 3 as (3 << 1) | 1
+        bus.store(7, TOHOST, 64).unwrap();
+        assert_eq!(htif.poll(&mut bus), Some(3));
+        bus.store(1, TOHOST, 64).unwrap();
+        assert_eq!(htif.poll(&mut bus), Some(0));
+    }
+}
@@ -5,4 +5,22 @@ pub mod processor;
 pub mod decode;
 pub mod errors;
 pub mod system_bus;
+pub mod device;
 pub mod dram;
+pub mod fpu;
+pub mod trap;
+pub mod mmu;
+pub mod tlb;
+pub mod pmp;
+pub mod clint;
+pub mod plic;
+pub mod uart;
+pub mod virtio;
+pub mod fdt;
+pub mod loader;
+pub mod htif;
+pub mod signature;
+pub mod sbi;
+
+#[cfg(test)]
+mod test_util;
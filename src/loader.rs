@@ -0,0 +1,215 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use elf::abi::{EI_CLASS, ELFCLASS64, EM_RISCV, PT_LOAD};
+use elf::endian::AnyEndian;
+use elf::ElfBytes;
+
+use crate::errors::LoaderError;
+use crate::system_bus::SystemBus;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+// Linux RISC-V Image header: text_offset at byte 8, magic2 at byte 56
+const IMAGE_TEXT_OFFSET: usize = 8;
+const IMAGE_MAGIC2_OFFSET: usize = 56;
+const IMAGE_MAGIC2: &[u8] = b"RSC\x05";
+// Where firmware puts kernels without a header, 2 MiB into RAM
+pub const DEFAULT_KERNEL_OFFSET: u64 = 0x20_0000;
+
+/// An ELF executable mapped into guest RAM.
+pub struct ElfImage {
+    entry: u64,
+    symbols: HashMap<String, u64>,
+}
+
+impl ElfImage {
+    pub fn new(entry: u64, symbols: HashMap<String, u64>) -> Self {
+        ElfImage { entry, symbols }
+    }
+
+    /// Address to start executing at.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Value of the symbol `name` from `.symtab`.
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn symbols(&self) -> &HashMap<String, u64> {
+        &self.symbols
+    }
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(ELF_MAGIC)
+}
+
+pub fn is_kernel_image(data: &[u8]) -> bool {
+    data.get(IMAGE_MAGIC2_OFFSET..IMAGE_MAGIC2_OFFSET + 4) == Some(IMAGE_MAGIC2)
+}
+
+/// Places a kernel `Image` at the offset into RAM its header asks for,
+/// or any other flat binary 2 MiB into RAM. Returns the entry point.
+pub fn load_kernel(bus: &mut SystemBus, data: &[u8]) -> Result<u64, LoaderError> {
+    let offset = if is_kernel_image(data) {
+        u64::from_le_bytes(data[IMAGE_TEXT_OFFSET..IMAGE_TEXT_OFFSET + 8].try_into().unwrap())
+    } else {
+        DEFAULT_KERNEL_OFFSET
+    };
+    let addr = bus.dram_base().checked_add(offset).ok_or(LoaderError::SegmentOutOfRange(offset))?;
+    bus.write_bytes(addr, data).map_err(|_| LoaderError::SegmentOutOfRange(addr))?;
+    Ok(addr)
+}
+
+/// Copies every PT_LOAD segment of `data` to its physical address,
+/// zero-filling the part of the segment beyond the file contents.
+pub fn load_elf(bus: &mut SystemBus, data: &[u8]) -> Result<ElfImage, LoaderError> {
+    // Checked ahead of parsing, the rest of the header depends on the class
+    if is_elf(data) && data.get(EI_CLASS) != Some(&ELFCLASS64) {
+        return Err(LoaderError::WrongClass);
+    }
+    let file = ElfBytes::<AnyEndian>::minimal_parse(data)?;
+    if file.ehdr.e_machine != EM_RISCV {
+        return Err(LoaderError::WrongMachine(file.ehdr.e_machine));
+    }
+
+    for segment in file.segments().iter().flat_map(|segments| segments.iter()) {
+        if segment.p_type != PT_LOAD || segment.p_memsz == 0 {
+            continue;
+        }
+        // The header sizes are untrusted, so check them against RAM before using them
+        let addr = segment.p_paddr;
+        let memsz = segment.p_memsz.max(segment.p_filesz);
+        let dram_end = bus.dram_base() + bus.dram_size() as u64;
+        match addr.checked_add(memsz) {
+            Some(end) if addr >= bus.dram_base() && end <= dram_end => {}
+            _ => return Err(LoaderError::SegmentOutOfRange(addr)),
+        }
+        let contents = file.segment_data(&segment)?;
+        let tail = vec![0; (memsz - contents.len() as u64) as usize];
+        bus.write_bytes(addr, contents)
+            .and_then(|_| bus.write_bytes(addr + contents.len() as u64, &tail))
+            .map_err(|_| LoaderError::SegmentOutOfRange(addr))?;
+    }
+
+    let mut symbols = HashMap::new();
+    if let Some((symtab, strtab)) = file.symbol_table()? {
+        for symbol in symtab.iter() {
+            match strtab.get(symbol.st_name as usize) {
+                Ok(name) if !name.is_empty() => {
+                    symbols.insert(name.to_string(), symbol.st_value);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(ElfImage::new(file.ehdr.e_entry, symbols))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::system_bus::SystemBusMap;
+
+    use super::*;
+
+    // A static executable with one segment (16 bytes in the file, 32 in
+    // memory) and a symbol table holding `tohost`
+    fn make_elf(class: u8, machine: u16) -> Vec<u8> {
+        let mut elf = vec![0; 0x340];
+        let mut put = |offset: usize, bytes: &[u8]| elf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        put(0, &[0x7f, b'E', b'L', b'F', class, 1, 1]);
+        put(16, &2u16.to_le_bytes());
+        put(18, &machine.to_le_bytes());
+        put(20, &1u32.to_le_bytes());
+        put(24, &0x8000_0004u64.to_le_bytes());
+        put(32, &64u64.to_le_bytes());
+        put(40, &0x280u64.to_le_bytes());
+        put(52, &[64, 0, 56, 0, 1, 0, 64, 0, 3, 0, 0, 0]);
+        // Program header
+        put(64, &PT_LOAD.to_le_bytes());
+        put(64 + 8, &0x100u64.to_le_bytes());
+        put(64 + 16, &0x8000_0000u64.to_le_bytes());
+        put(64 + 24, &0x8000_0000u64.to_le_bytes());
+        put(64 + 32, &16u64.to_le_bytes());
+        put(64 + 40, &32u64.to_le_bytes());
+        put(0x100, &[0xaa; 16]);
+        // .symtab: the null symbol, then tohost
+        put(0x200 + 24, &1u32.to_le_bytes());
+        put(0x200 + 24 + 8, &0x8000_1000u64.to_le_bytes());
+        put(0x240, b"\0tohost\0");
+        // Section headers: null, .symtab linked to .strtab
+        let symtab = 0x280 + 64;
+        put(symtab + 4, &elf::abi::SHT_SYMTAB.to_le_bytes());
+        put(symtab + 24, &0x200u64.to_le_bytes());
+        put(symtab + 32, &48u64.to_le_bytes());
+        put(symtab + 40, &2u32.to_le_bytes());
+        put(symtab + 56, &24u64.to_le_bytes());
+        let strtab = 0x280 + 128;
+        put(strtab + 4, &elf::abi::SHT_STRTAB.to_le_bytes());
+        put(strtab + 24, &0x240u64.to_le_bytes());
+        put(strtab + 32, &8u64.to_le_bytes());
+        elf
+    }
+
+    fn make_bus() -> SystemBus {
+        SystemBus::new(SystemBusMap { dram_base_addr: 0x8000_0000, dram_size: 0x1_0000 })
+    }
+
+    #[test]
+    fn load_elf_test() {
+        let mut bus = make_bus();
+        bus.write_bytes(0x8000_0000, &[0xff; 64]).unwrap();
+        let elf = make_elf(2, EM_RISCV);
+        assert!(is_elf(&elf));
+        let image = load_elf(&mut bus, &elf).unwrap();
+        assert_eq!(image.entry(), 0x8000_0004);
+        assert_eq!(image.symbol("tohost"), Some(0x8000_1000));
+        assert_eq!(image.symbol("fromhost"), None);
+        assert_eq!(bus.load(0x8000_0008, 64).unwrap(), 0xaaaa_aaaa_aaaa_aaaa);
+        // .bss is cleared, what lies past the segment is not
+        assert_eq!(bus.load(0x8000_0018, 64).unwrap(), 0);
+        assert_eq!(bus.load(0x8000_0020, 64).unwrap(), u64::MAX);
+        assert_eq!(bus.dram_size(), 0x1_0000);
+    }
+
+    #[test]
+    fn load_kernel_test() {
+        let mut bus = make_bus();
+        let mut image = vec![0; 64];
+        image[0..4].copy_from_slice(&0x13u32.to_le_bytes());
+        image[8..16].copy_from_slice(&0x1000u64.to_le_bytes());
+        image[56..60].copy_from_slice(IMAGE_MAGIC2);
+        assert_eq!(load_kernel(&mut bus, &image).unwrap(), 0x8000_1000);
+        assert_eq!(bus.load(0x8000_1000, 32).unwrap(), 0x13);
+        // Without a header it lands 2 MiB in, past the end of this RAM
+        assert!(matches!(load_kernel(&mut bus, &[0x13, 0, 0, 0]),
+                         Err(LoaderError::SegmentOutOfRange(0x8020_0000))));
+        // A text_offset that wraps the address space
+        image[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(load_kernel(&mut bus, &image), Err(LoaderError::SegmentOutOfRange(u64::MAX))));
+    }
+
+    #[test]
+    fn load_elf_mismatch_test() {
+        let mut bus = make_bus();
+        assert!(matches!(load_elf(&mut bus, &make_elf(1, EM_RISCV)), Err(LoaderError::WrongClass)));
+        assert!(matches!(load_elf(&mut bus, &make_elf(2, 62)), Err(LoaderError::WrongMachine(62))));
+        assert!(matches!(load_elf(&mut bus, b"not an elf"), Err(LoaderError::Parse(_))));
+    }
+
+    #[test]
+    fn load_elf_oversized_test() {
+        let mut bus = make_bus();
+        // p_memsz far beyond RAM, then one that wraps around
+        let mut elf = make_elf(2, EM_RISCV);
+        elf[64 + 40..64 + 48].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert!(matches!(load_elf(&mut bus, &elf), Err(LoaderError::SegmentOutOfRange(0x8000_0000))));
+        elf[64 + 40..64 + 48].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(load_elf(&mut bus, &elf), Err(LoaderError::SegmentOutOfRange(0x8000_0000))));
+    }
+}
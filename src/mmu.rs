@@ -0,0 +1,148 @@
+#![allow(dead_code)]
+
+use crate::opcodes::*;
+use crate::pmp;
+use crate::processor::Mode;
+use crate::system_bus::SystemBus;
+use crate::trap::Exception;
+
+pub const PAGE_SIZE: u64 = 4096;
+const PTE_SIZE: u64 = 8;
+
+pub const PTE_V: u64 = 0x1 << 0;
+pub const PTE_R: u64 = 0x1 << 1;
+pub const PTE_W: u64 = 0x1 << 2;
+pub const PTE_X: u64 = 0x1 << 3;
+pub const PTE_U: u64 = 0x1 << 4;
+pub const PTE_G: u64 = 0x1 << 5;
+pub const PTE_A: u64 = 0x1 << 6;
+pub const PTE_D: u64 = 0x1 << 7;
+// PBMT and N are not implemented, so these bits must be zero
+const PTE_RESERVED: u64 = 0x3ff << 54;
+const PTE_PPN_SHIFT: u64 = 10;
+const PTE_PPN_MASK: u64 = (0x1 << 44) - 1;
+
+const VPN_BITS: u64 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl AccessType {
+    pub fn page_fault(self, vaddr: u64) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionPageFault(vaddr),
+            AccessType::Load => Exception::LoadPageFault(vaddr),
+            AccessType::Store => Exception::StorePageFault(vaddr),
+        }
+    }
+
+    pub fn access_fault(self, vaddr: u64) -> Exception {
+        match self {
+            AccessType::Fetch => Exception::InstructionAccessFault(vaddr),
+            AccessType::Load => Exception::LoadAccessFault(vaddr),
+            AccessType::Store => Exception::StoreAccessFault(vaddr),
+        }
+    }
+}
+
+/// Number of page-table levels for a `satp.MODE`, `None` if unsupported.
+pub fn levels(satp_mode: u64) -> Option<u64> {
+    match satp_mode {
+        SATP_MODE_SV39 => Some(3),
+        SATP_MODE_SV48 => Some(4),
+        SATP_MODE_SV57 => Some(5),
+        _ => None,
+    }
+}
+
+/// Translates `vaddr` for an access performed at privilege `mode`.
+/// M-mode and `satp.MODE = Bare` use physical addresses as is.
+pub fn translate(bus: &mut SystemBus, csrs: &[u64], mode: Mode,
+                 vaddr: u64, access: AccessType) -> Result<u64, Exception> {
+    let satp = csrs[SATP as usize];
+    let mstatus = csrs[MSTATUS as usize];
+    if mode == Mode::Machine {
+        return Ok(vaddr);
+    }
+    let levels = match levels((satp & SATP_MODE) >> 60) {
+        Some(levels) => levels,
+        None => return Ok(vaddr),
+    };
+
+    // Bits above the virtual address width must copy its top bit
+    let va_bits = 12 + VPN_BITS * levels;
+    let upper = (vaddr as i64) >> (va_bits - 1);
+    if upper != 0 && upper != -1 {
+        return Err(access.page_fault(vaddr));
+    }
+
+    let mut table = (satp & SATP_PPN) * PAGE_SIZE;
+    let mut level = levels - 1;
+    let (pte, pte_addr) = loop {
+        let vpn = (vaddr >> (12 + VPN_BITS * level)) & ((0x1 << VPN_BITS) - 1);
+        let pte_addr = table + vpn * PTE_SIZE;
+        // The walk itself is an S-mode access as far as PMP is concerned
+        if !pmp::check(csrs, pte_addr, PTE_SIZE, AccessType::Load, Mode::Supervisor) {
+            return Err(access.access_fault(vaddr));
+        }
+        let pte = bus.load(pte_addr, 64).map_err(|_| access.access_fault(vaddr))?;
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) || pte & PTE_RESERVED != 0 {
+            return Err(access.page_fault(vaddr));
+        }
+        if pte & (PTE_R | PTE_X) != 0 {
+            break (pte, pte_addr);
+        }
+        // Pointer to the next level, whose A/D/U bits are reserved
+        if level == 0 || pte & (PTE_A | PTE_D | PTE_U) != 0 {
+            return Err(access.page_fault(vaddr));
+        }
+        level -= 1;
+        table = ((pte >> PTE_PPN_SHIFT) & PTE_PPN_MASK) * PAGE_SIZE;
+    };
+
+    if !permitted(pte, mstatus, mode, access) {
+        return Err(access.page_fault(vaddr));
+    }
+
+    // A superpage must be aligned to its own size
+    let ppn = (pte >> PTE_PPN_SHIFT) & PTE_PPN_MASK;
+    let offset_mask = (0x1 << (12 + VPN_BITS * level)) - 1;
+    if (ppn * PAGE_SIZE) & offset_mask != 0 {
+        return Err(access.page_fault(vaddr));
+    }
+
+    // Hardware update of the accessed and dirty bits
+    let mut updated = pte | PTE_A;
+    if access == AccessType::Store {
+        updated |= PTE_D;
+    }
+    if updated != pte {
+        if !pmp::check(csrs, pte_addr, PTE_SIZE, AccessType::Store, Mode::Supervisor) {
+            return Err(access.access_fault(vaddr));
+        }
+        bus.store(updated, pte_addr, 64).map_err(|_| access.access_fault(vaddr))?;
+    }
+
+    Ok((ppn * PAGE_SIZE) | (vaddr & offset_mask))
+}
+
+fn permitted(pte: u64, mstatus: u64, mode: Mode, access: AccessType) -> bool {
+    if pte & PTE_U != 0 {
+        // S-mode never executes user pages and touches their data only with SUM
+        if mode == Mode::Supervisor
+            && (access == AccessType::Fetch || mstatus & MSTATUS_SUM == 0) {
+            return false;
+        }
+    } else if mode == Mode::User {
+        return false;
+    }
+    match access {
+        AccessType::Fetch => pte & PTE_X != 0,
+        AccessType::Load => pte & PTE_R != 0 || (mstatus & MSTATUS_MXR != 0 && pte & PTE_X != 0),
+        AccessType::Store => pte & PTE_W != 0,
+    }
+}
@@ -56,6 +56,15 @@ pub const R_TYPE: u64 = 0x33;
         pub const SRA: u64 = 0x20;
     pub const OR : u64 = 0x6;
     pub const AND: u64 = 0x7;
+    pub const MULDIV: u64 = 0x01;
+        pub const MUL:    u64 = 0x0;
+        pub const MULH:   u64 = 0x1;
+        pub const MULHSU: u64 = 0x2;
+        pub const MULHU:  u64 = 0x3;
+        pub const DIV:    u64 = 0x4;
+        pub const DIVU:   u64 = 0x5;
+        pub const REM:    u64 = 0x6;
+        pub const REMU:   u64 = 0x7;
 
 pub const FENCE: u64 = 0x0f;
 pub const FENCE_FUNCT3: u64 = 0x00;
@@ -83,11 +92,15 @@ pub const REMW:  u64 = 0x6;
 pub const REMUW: u64 = 0x7;
 
 pub const SYSTEM: u64 =       0x73;
-pub const ECALLBREAK: u64 =   0x00;
-pub const ECALL: u64 =            0x00;
-pub const EBREAK: u64 =           0x01;
-pub const MRET: u64 =             0x08;
-pub const SRET: u64 =             0x18;
+pub const PRIV: u64 =         0x00;
+// funct12
+pub const ECALL: u64 =            0x000;
+pub const EBREAK: u64 =           0x001;
+pub const SRET: u64 =             0x102;
+pub const WFI: u64 =              0x105;
+pub const MRET: u64 =             0x302;
+// funct7
+pub const SFENCE_VMA: u64 =       0x09;
 pub const CSRRW: u64 =        0x01;
 pub const CSRRS: u64 =        0x02;
 pub const CSRRC: u64 =        0x03;
@@ -95,7 +108,54 @@ pub const CSRRWI: u64 =       0x05;
 pub const CSRRSI: u64 =       0x06;
 pub const CSRRCI: u64 =       0x07;
 
+pub const LOAD_FP: u64 = 0x07;
+    pub const FLW: u64 = 0x2;
+    pub const FLD: u64 = 0x3;
+
+pub const STORE_FP: u64 = 0x27;
+    pub const FSW: u64 = 0x2;
+    pub const FSD: u64 = 0x3;
+
+pub const FMADD:  u64 = 0x43;
+pub const FMSUB:  u64 = 0x47;
+pub const FNMSUB: u64 = 0x4b;
+pub const FNMADD: u64 = 0x4f;
+    pub const FMT_S: u64 = 0x0;
+    pub const FMT_D: u64 = 0x1;
+
+pub const OP_FP: u64 = 0x53;
+    pub const FADD:  u64 = 0x00;
+    pub const FSUB:  u64 = 0x01;
+    pub const FMUL:  u64 = 0x02;
+    pub const FDIV:  u64 = 0x03;
+    pub const FSGNJ: u64 = 0x04;
+        pub const FSGNJ_J:  u64 = 0x0;
+        pub const FSGNJ_N:  u64 = 0x1;
+        pub const FSGNJ_X:  u64 = 0x2;
+    pub const FMINMAX: u64 = 0x05;
+        pub const FMIN: u64 = 0x0;
+        pub const FMAX: u64 = 0x1;
+    pub const FCVT_FMT_FMT: u64 = 0x08;
+    pub const FSQRT: u64 = 0x0b;
+    pub const FCMP:  u64 = 0x14;
+        pub const FLE: u64 = 0x0;
+        pub const FLT: u64 = 0x1;
+        pub const FEQ: u64 = 0x2;
+    pub const FCVT_INT_FMT: u64 = 0x18;
+    pub const FCVT_FMT_INT: u64 = 0x1a;
+        pub const FCVT_W:  u64 = 0x0;
+        pub const FCVT_WU: u64 = 0x1;
+        pub const FCVT_L:  u64 = 0x2;
+        pub const FCVT_LU: u64 = 0x3;
+    pub const FMV_X_FCLASS: u64 = 0x1c;
+        pub const FMV_X:  u64 = 0x0;
+        pub const FCLASS: u64 = 0x1;
+    pub const FMV_FMT_X: u64 = 0x1e;
+
 pub const AMO_W:     u64 = 0x2f;
+// funct3 selects the width, funct5 values are shared by the .W and .D forms
+pub const AMO_WIDTH_W: u64 = 0x2;
+pub const AMO_WIDTH_D: u64 = 0x3;
 pub const LR_W:      u64 =    0x02;
 pub const SC_W:      u64 =    0x03;
 pub const AMOSWAP_W: u64 =    0x01;
@@ -108,19 +168,111 @@ pub const AMOMAX_W:  u64 =    0x14;
 pub const AMOMINU_W: u64 =    0x18;
 pub const AMOMAXU_W: u64 =    0x1c;
 
+pub const FFLAGS: u64 = 0x001;
+pub const FRM: u64    = 0x002;
+pub const FCSR: u64   = 0x003;
+
+// Compressed quadrants and their funct3
+pub const C0: u64 = 0x0;
+    pub const C_ADDI4SPN: u64 = 0x0;
+    pub const C_FLD: u64 = 0x1;
+    pub const C_LW:  u64 = 0x2;
+    pub const C_LD:  u64 = 0x3;
+    pub const C_FSD: u64 = 0x5;
+    pub const C_SW:  u64 = 0x6;
+    pub const C_SD:  u64 = 0x7;
+pub const C1: u64 = 0x1;
+    pub const C_ADDI:  u64 = 0x0;
+    pub const C_ADDIW: u64 = 0x1;
+    pub const C_LI:    u64 = 0x2;
+    pub const C_LUI_ADDI16SP: u64 = 0x3;
+    pub const C_MISC_ALU: u64 = 0x4;
+        pub const C_SRLI: u16 = 0x0;
+        pub const C_SRAI: u16 = 0x1;
+        pub const C_ANDI: u16 = 0x2;
+            pub const C_SUB: u16 = 0x0;
+            pub const C_XOR: u16 = 0x1;
+            pub const C_OR:  u16 = 0x2;
+            pub const C_AND: u16 = 0x3;
+            pub const C_SUBW: u16 = 0x0;
+            pub const C_ADDW: u16 = 0x1;
+    pub const C_J:    u64 = 0x5;
+    pub const C_BEQZ: u64 = 0x6;
+    pub const C_BNEZ: u64 = 0x7;
+pub const C2: u64 = 0x2;
+    pub const C_SLLI:  u64 = 0x0;
+    pub const C_FLDSP: u64 = 0x1;
+    pub const C_LWSP:  u64 = 0x2;
+    pub const C_LDSP:  u64 = 0x3;
+    pub const C_JR_MV_ADD: u64 = 0x4;
+    pub const C_FSDSP: u64 = 0x5;
+    pub const C_SWSP:  u64 = 0x6;
+    pub const C_SDSP:  u64 = 0x7;
+
+pub const SSTATUS: u64    = 0x100;
+pub const SIE: u64        = 0x104;
+pub const STVEC: u64      = 0x105;
+pub const SCOUNTEREN: u64 = 0x106;
+pub const SSCRATCH: u64   = 0x140;
+pub const SEPC: u64       = 0x141;
+pub const SCAUSE: u64     = 0x142;
+pub const STVAL: u64      = 0x143;
+pub const SIP: u64        = 0x144;
+pub const SATP: u64       = 0x180;
+    pub const SATP_PPN: u64 = (0x1 << 44) - 1;
+    pub const SATP_MODE: u64 = 0xf << 60;
+    pub const SATP_MODE_BARE: u64 = 0x0;
+    pub const SATP_MODE_SV39: u64 = 0x8;
+    pub const SATP_MODE_SV48: u64 = 0x9;
+    pub const SATP_MODE_SV57: u64 = 0xa;
+
+pub const TIME: u64 = 0xc01;
+
 pub const MVENDORID: u64 = 0xf11;
 pub const MARCHID: u64   = 0xf12;
 pub const MIMPID: u64    = 0xf13;
 pub const MHARTID: u64   = 0xf14;
 
 pub const MSTATUS: u64 = 0x300;
+    pub const MSTATUS_SIE: u64 = 0x1 << 1;
+    pub const MSTATUS_MIE: u64 = 0x1 << 3;
+    pub const MSTATUS_SPIE: u64 = 0x1 << 5;
+    pub const MSTATUS_MPIE: u64 = 0x1 << 7;
+    pub const MSTATUS_SPP: u64 = 0x1 << 8;
+    pub const MSTATUS_MPP: u64 = 0x3 << 11;
+    pub const MSTATUS_FS: u64 = 0x3 << 13;
+    pub const MSTATUS_MPRV: u64 = 0x1 << 17;
+    pub const MSTATUS_SUM: u64 = 0x1 << 18;
+    pub const MSTATUS_MXR: u64 = 0x1 << 19;
+    pub const MSTATUS_TVM: u64 = 0x1 << 20;
+    pub const MSTATUS_TW: u64 = 0x1 << 21;
+    pub const MSTATUS_TSR: u64 = 0x1 << 22;
+    pub const MSTATUS_UXL: u64 = 0x3 << 32;
+    pub const MSTATUS_SXL: u64 = 0x3 << 34;
+    pub const MSTATUS_SD: u64 = 0x1 << 63;
+pub const MISA: u64 = 0x301;
+    pub const MISA_S: u64 = 0x1 << 18;
 pub const MEDELEG: u64 = 0x302;
 pub const MIDELEG:  u64 = 0x303;
 pub const MIE: u64 = 0x304;
 pub const MTVEC: u64 = 0x305;
+pub const MCOUNTEREN: u64 = 0x306;
 
 pub const MSCRATCH: u64 = 0x340;
 pub const MEPC: u64 = 0x341;
 pub const MCAUSE: u64 = 0x342;
 pub const MTVAL: u64 = 0x343;
 pub const MIP: u64 = 0x344;
+    pub const MIP_SSIP: u64 = 0x1 << 1;
+    pub const MIP_MSIP: u64 = 0x1 << 3;
+    pub const MIP_STIP: u64 = 0x1 << 5;
+    pub const MIP_MTIP: u64 = 0x1 << 7;
+    pub const MIP_SEIP: u64 = 0x1 << 9;
+    pub const MIP_MEIP: u64 = 0x1 << 11;
+
+pub const PMPCFG0: u64 = 0x3a0;
+pub const PMPCFG2: u64 = 0x3a2;
+pub const PMPCFG15: u64 = 0x3af;
+pub const PMPADDR0: u64 = 0x3b0;
+pub const PMPADDR1: u64 = 0x3b1;
+pub const PMPADDR63: u64 = 0x3ef;
@@ -0,0 +1,200 @@
+#![allow(dead_code)]
+
+use crate::device::Device;
+use crate::errors::SystemBusError;
+
+pub const PLIC_BASE: u64 = 0x0c00_0000;
+pub const PLIC_SIZE: u64 = 0x400_0000;
+
+// Register layout of the SiFive PLIC
+const PRIORITY_BASE: u64 = 0x00_0000;
+const PENDING_BASE: u64 = 0x00_1000;
+const ENABLE_BASE: u64 = 0x00_2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT_BASE: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+const THRESHOLD: u64 = 0x0;
+const CLAIM_COMPLETE: u64 = 0x4;
+
+// Source 0 does not exist
+pub const PLIC_SOURCES: usize = 1024;
+pub const PLIC_MAX_HARTS: usize = 8;
+const WORDS: usize = PLIC_SOURCES / 32;
+
+/// Context of the M-mode (`s_mode` false) or S-mode external interrupt
+/// line of a hart.
+pub fn context(hartid: u64, s_mode: bool) -> usize {
+    2 * hartid as usize + s_mode as usize
+}
+
+/// Platform-level interrupt controller. Sources are level-triggered:
+/// a source is pending while its line is high, unless it has been
+/// claimed and not yet completed.
+pub struct Plic {
+    priority: Vec<u32>,
+    pending: [u32; WORDS],
+    level: [u32; WORDS],
+    claimed: [u32; WORDS],
+    enable: Vec<[u32; WORDS]>,
+    threshold: Vec<u32>,
+}
+
+fn bit(bits: &[u32; WORDS], source: usize) -> bool {
+    bits[source / 32] & (0x1 << (source % 32)) != 0
+}
+
+fn set_bit(bits: &mut [u32; WORDS], source: usize, value: bool) {
+    if value {
+        bits[source / 32] |= 0x1 << (source % 32);
+    } else {
+        bits[source / 32] &= !(0x1 << (source % 32));
+    }
+}
+
+impl Plic {
+    pub fn new() -> Self {
+        Plic {
+            priority: vec![0; PLIC_SOURCES],
+            pending: [0; WORDS],
+            level: [0; WORDS],
+            claimed: [0; WORDS],
+            enable: vec![[0; WORDS]; 2 * PLIC_MAX_HARTS],
+            threshold: vec![0; 2 * PLIC_MAX_HARTS],
+        }
+    }
+
+    /// Drives the interrupt line of `source`.
+    pub fn set_irq(&mut self, source: usize, level: bool) {
+        if source == 0 || source >= PLIC_SOURCES {
+            return;
+        }
+        set_bit(&mut self.level, source, level);
+        if !bit(&self.claimed, source) {
+            set_bit(&mut self.pending, source, level);
+        }
+    }
+
+    // Highest-priority pending source enabled for `context` and above its
+    // threshold; the lowest id wins a tie
+    fn best(&self, context: usize) -> Option<usize> {
+        let enable = self.enable.get(context)?;
+        let mut best: Option<usize> = None;
+        for (word, (pending, enable)) in self.pending.iter().zip(enable).enumerate() {
+            // Checked on every tick, so skip quiet words wholesale
+            let mut bits = pending & enable;
+            while bits != 0 {
+                let source = word * 32 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let priority = self.priority[source];
+                if priority > self.threshold[context]
+                    && best.is_none_or(|b| priority > self.priority[b]) {
+                    best = Some(source);
+                }
+            }
+        }
+        best
+    }
+
+    pub fn irq_pending_source(&self, source: usize) -> bool {
+        source < PLIC_SOURCES && bit(&self.pending, source)
+    }
+
+    /// Whether the external interrupt line of `context` is asserted.
+    pub fn irq_pending(&self, context: usize) -> bool {
+        self.best(context).is_some()
+    }
+
+    pub fn claim(&mut self, context: usize) -> u32 {
+        match self.best(context) {
+            Some(source) => {
+                set_bit(&mut self.pending, source, false);
+                set_bit(&mut self.claimed, source, true);
+                source as u32
+            }
+            None => 0,
+        }
+    }
+
+    pub fn complete(&mut self, context: usize, source: u32) {
+        let source = source as usize;
+        let enabled = self.enable.get(context).is_some_and(|enable| bit(enable, source));
+        if source == 0 || source >= PLIC_SOURCES || !enabled {
+            return;
+        }
+        set_bit(&mut self.claimed, source, false);
+        if bit(&self.level, source) {
+            set_bit(&mut self.pending, source, true);
+        }
+    }
+}
+
+impl Device for Plic {
+    fn load(&mut self, offset: u64, size: usize) -> Result<u64, SystemBusError> {
+        // All registers are 32 bits wide
+        if size != 32 {
+            return Err(SystemBusError::InvalidAddress);
+        }
+        let contexts = self.threshold.len() as u64;
+        let value = match offset {
+            PRIORITY_BASE..PENDING_BASE => self.priority[(offset / 4) as usize],
+            PENDING_BASE..ENABLE_BASE if offset < PENDING_BASE + 4 * WORDS as u64 => {
+                self.pending[((offset - PENDING_BASE) / 4) as usize]
+            }
+            ENABLE_BASE..CONTEXT_BASE => {
+                let context = ((offset - ENABLE_BASE) / ENABLE_STRIDE) as usize;
+                let word = ((offset - ENABLE_BASE) % ENABLE_STRIDE / 4) as usize;
+                self.enable.get(context).ok_or(SystemBusError::InvalidAddress)?[word]
+            }
+            _ if offset >= CONTEXT_BASE && offset < CONTEXT_BASE + contexts * CONTEXT_STRIDE => {
+                let context = ((offset - CONTEXT_BASE) / CONTEXT_STRIDE) as usize;
+                match (offset - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    THRESHOLD => self.threshold[context],
+                    CLAIM_COMPLETE => self.claim(context),
+                    _ => 0,
+                }
+            }
+            _ => return Err(SystemBusError::InvalidAddress),
+        };
+        Ok(value as u64)
+    }
+
+    fn store(&mut self, data: u64, offset: u64, size: usize) -> Result<(), SystemBusError> {
+        if size != 32 {
+            return Err(SystemBusError::InvalidAddress);
+        }
+        let data = data as u32;
+        let contexts = self.threshold.len() as u64;
+        match offset {
+            PRIORITY_BASE..PENDING_BASE => {
+                if offset != 0 {
+                    self.priority[(offset / 4) as usize] = data;
+                }
+            }
+            // Pending bits are read-only
+            PENDING_BASE..ENABLE_BASE => {}
+            ENABLE_BASE..CONTEXT_BASE => {
+                let context = ((offset - ENABLE_BASE) / ENABLE_STRIDE) as usize;
+                let word = ((offset - ENABLE_BASE) % ENABLE_STRIDE / 4) as usize;
+                let enable = self.enable.get_mut(context).ok_or(SystemBusError::InvalidAddress)?;
+                // Source 0 is hardwired to zero
+                enable[word] = if word == 0 { data & !0x1 } else { data };
+            }
+            _ if offset >= CONTEXT_BASE && offset < CONTEXT_BASE + contexts * CONTEXT_STRIDE => {
+                let context = ((offset - CONTEXT_BASE) / CONTEXT_STRIDE) as usize;
+                match (offset - CONTEXT_BASE) % CONTEXT_STRIDE {
+                    THRESHOLD => self.threshold[context] = data,
+                    CLAIM_COMPLETE => self.complete(context, data),
+                    _ => {}
+                }
+            }
+            _ => return Err(SystemBusError::InvalidAddress),
+        }
+        Ok(())
+    }
+}
+
+impl Default for Plic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,83 @@
+#![allow(dead_code)]
+
+use crate::mmu::AccessType;
+use crate::opcodes::*;
+use crate::processor::Mode;
+
+pub const PMP_ENTRIES: usize = 64;
+
+pub const PMP_R: u8 = 0x1 << 0;
+pub const PMP_W: u8 = 0x1 << 1;
+pub const PMP_X: u8 = 0x1 << 2;
+pub const PMP_A: u8 = 0x3 << 3;
+    pub const PMP_A_OFF: u8 = 0x0 << 3;
+    pub const PMP_A_TOR: u8 = 0x1 << 3;
+    pub const PMP_A_NA4: u8 = 0x2 << 3;
+    pub const PMP_A_NAPOT: u8 = 0x3 << 3;
+pub const PMP_L: u8 = 0x1 << 7;
+
+// pmpaddr holds bits 55:2 of the address
+pub const PMPADDR_MASK: u64 = (0x1 << 54) - 1;
+
+/// Configuration byte of entry `i`. On RV64 only the even pmpcfg
+/// registers exist, each holding eight entries.
+pub fn cfg(csrs: &[u64], i: usize) -> u8 {
+    (csrs[PMPCFG0 as usize + (i / 8) * 2] >> (8 * (i % 8))) as u8
+}
+
+/// Whether writes to pmpaddr `i` are ignored: the entry is locked, or
+/// it is the bottom of a locked TOR range.
+pub fn addr_locked(csrs: &[u64], i: usize) -> bool {
+    if cfg(csrs, i) & PMP_L != 0 {
+        return true;
+    }
+    i + 1 < PMP_ENTRIES && cfg(csrs, i + 1) & (PMP_L | PMP_A) == PMP_L | PMP_A_TOR
+}
+
+// Address range [start, end) matched by entry `i`
+fn range(csrs: &[u64], i: usize) -> Option<(u64, u64)> {
+    let addr = csrs[PMPADDR0 as usize + i];
+    match cfg(csrs, i) & PMP_A {
+        PMP_A_TOR => {
+            let start = if i == 0 { 0 } else { csrs[PMPADDR0 as usize + i - 1] << 2 };
+            Some((start, addr << 2))
+        }
+        PMP_A_NA4 => Some((addr << 2, (addr << 2) + 4)),
+        PMP_A_NAPOT => {
+            let size = 0x8 << addr.trailing_ones();
+            let start = (addr & !((0x1 << addr.trailing_ones()) - 1)) << 2;
+            Some((start, start + size))
+        }
+        _ => None,
+    }
+}
+
+/// Checks an access of `size` bytes at physical `addr` made at privilege
+/// `mode`. The lowest-numbered matching entry decides, and it must cover
+/// every byte of the access. Unmatched accesses succeed in M-mode only,
+/// so S and U-mode need an entry granting them memory before running.
+pub fn check(csrs: &[u64], addr: u64, size: u64, access: AccessType, mode: Mode) -> bool {
+    for i in 0..PMP_ENTRIES {
+        let (start, end) = match range(csrs, i) {
+            Some(range) => range,
+            None => continue,
+        };
+        if addr.saturating_add(size) <= start || addr >= end {
+            continue;
+        }
+        if addr < start || addr.saturating_add(size) > end {
+            return false;
+        }
+        let cfg = cfg(csrs, i);
+        if mode == Mode::Machine && cfg & PMP_L == 0 {
+            return true;
+        }
+        let bit = match access {
+            AccessType::Fetch => PMP_X,
+            AccessType::Load => PMP_R,
+            AccessType::Store => PMP_W,
+        };
+        return cfg & bit != 0;
+    }
+    mode == Mode::Machine
+}
@@ -5,10 +5,22 @@
 use std::ops::Shl;
 use std::ops::Shr;
 
+use rustc_apfloat::ieee::{Double, Single};
+use rustc_apfloat::{FloatConvert, Round, StatusAnd};
+
 use crate::errors::*;
+use crate::fdt;
+use crate::htif::Htif;
+use crate::sbi::{Sbi, SbiEffect, SBI_ERR_INVALID_PARAM};
+use crate::fpu::*;
+use crate::mmu::{self, AccessType};
+use crate::tlb::{Tlb, TlbStats};
+use crate::plic;
+use crate::pmp::{self, PMP_A_NAPOT, PMP_L, PMP_R, PMP_W, PMP_X, PMPADDR_MASK};
 use crate::opcodes::*;
 use crate::decode::*;
 use crate::system_bus::*;
+use crate::trap::*;
 
 const NREGS: usize = 32;
 const NSREGS: usize = 4096;
@@ -21,75 +33,642 @@ enum Inst {
     Inst64(u64),
 }
 
+// Privilege levels, ordered by their encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mode {
+    User = 0x0,
+    Supervisor = 0x1,
+    Machine = 0x3,
+}
+
+impl Mode {
+    fn from_bits(bits: u64) -> Mode {
+        match bits & 0x3 {
+            0x0 => Mode::User,
+            0x1 => Mode::Supervisor,
+            _ => Mode::Machine,
+        }
+    }
+}
+
+// RV64 with the A, C, D, F, I, M, S and U extensions
+const MISA_VALUE: u64 = (0x2 << 62)
+    | (1 << 0) | (1 << 2) | (1 << 3) | (1 << 5)
+    | (1 << 8) | (1 << 12) | (1 << 18) | (1 << 20);
+
+const SSTATUS_MASK: u64 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_FS
+    | MSTATUS_SUM | MSTATUS_MXR | MSTATUS_UXL | MSTATUS_SD;
+
+const MSTATUS_WRITABLE: u64 = MSTATUS_SIE | MSTATUS_MIE | MSTATUS_SPIE | MSTATUS_MPIE
+    | MSTATUS_SPP | MSTATUS_MPP | MSTATUS_FS | MSTATUS_MPRV | MSTATUS_SUM
+    | MSTATUS_MXR | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+
+// Environment calls from M-mode can never be delegated
+const MEDELEG_WRITABLE: u64 = 0xb3ff;
+
+// Only supervisor interrupts can be delegated
+const MIDELEG_WRITABLE: u64 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+
+const MIE_WRITABLE: u64 = MIP_SSIP | MIP_MSIP | MIP_STIP | MIP_MTIP | MIP_SEIP | MIP_MEIP;
+
 pub struct Processor {
     regs: [u64; NREGS],
+    fregs: [u64; NREGS],
 
     pc: u64,
+    mode: Mode,
+    // Parked by WFI until an interrupt becomes pending
+    wfi: bool,
+    // S-mode external interrupt line from the PLIC, or'ed into mip.SEIP
+    // next to the bit software writes
+    seip: bool,
     system_bus: SystemBus,
+    tlb: Tlb,
+    htif: Option<Htif>,
+    // Built-in firmware answering S-mode ECALLs, see boot_supervisor()
+    sbi: Option<Sbi>,
 
     csrs: [u64; NSREGS],
 }
 
 impl Processor {
     pub fn new(system_bus: SystemBus) -> Self {
+        let mut csrs = [0; NSREGS];
+        csrs[MISA as usize] = MISA_VALUE;
+        csrs[MSTATUS as usize] = MSTATUS_UXL | MSTATUS_SXL;
         Processor {
             regs: [0; NREGS],
+            fregs: [0; NREGS],
             pc: 0,
+            mode: Mode::Machine,
+            wfi: false,
+            seip: false,
             system_bus,
-            csrs: [0; NSREGS],
+            tlb: Tlb::new(),
+            htif: None,
+            sbi: None,
+            csrs,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.wfi
+    }
+
+    pub fn tlb_stats(&self) -> TlbStats {
+        self.tlb.stats()
+    }
+
+    pub fn system_bus_mut(&mut self) -> &mut SystemBus {
+        &mut self.system_bus
+    }
+
+    // Cached translations were checked against the old privilege level
+    fn set_mode(&mut self, mode: Mode) {
+        if mode != self.mode {
+            self.tlb.flush();
         }
+        self.mode = mode;
     }
 
     pub fn set_pc(&mut self, pc: u64) {
         self.pc = pc;
     }
 
-    // TODO: Определение типа инструкции за один fetch
-    // TODO: Заворачивание ошибки доступа к шине в ошибку процессора
-    fn fetch(&self) -> Result<Inst, ProcessorError> {
-        let inst = self.system_bus.load(self.pc, 64).map_err(|err| {
-            ProcessorError::FetchError
-        })?;
-        if inst & 0x3 < 0x3 {
-            return Ok(Inst::Inst16(inst as u16));
+    /// Hands over to a kernel at `entry` in S-mode the way SBI firmware
+    /// would, with the built-in SBI answering its ECALLs. Everything that
+    /// can be delegated is, as there is no M-mode code to handle it.
+    pub fn boot_supervisor(&mut self, entry: u64) {
+        self.sbi = Some(Sbi::new());
+        let ecall_s = 0x1 << Exception::EnvironmentCallFromSMode.code();
+        self.csrs[MEDELEG as usize] = MEDELEG_WRITABLE & !ecall_s;
+        self.csrs[MIDELEG as usize] = MIDELEG_WRITABLE;
+        // cycle, time and instret
+        self.csrs[MCOUNTEREN as usize] = 0x7;
+        self.pmp_allow_all();
+        self.set_mode(Mode::Supervisor);
+        self.pc = entry;
+    }
+
+    // A single NAPOT entry spanning the address space, as OpenSBI sets up
+    // before handing S-mode all of memory
+    fn pmp_allow_all(&mut self) {
+        self.csrs[PMPADDR0 as usize] = PMPADDR_MASK;
+        self.csrs[PMPCFG0 as usize] = (PMP_A_NAPOT | PMP_R | PMP_W | PMP_X) as u64;
+    }
+
+    /// Serves HTIF requests after every instruction, `tick()` fails with
+    /// `ProcessorError::Exit` once the guest asks to stop.
+    pub fn attach_htif(&mut self, htif: Htif) {
+        self.htif = Some(htif);
+    }
+
+    /// Places a device tree of the machine at the top of RAM and passes
+    /// it the way firmware and kernels expect: hart id in a0, DTB
+    /// address in a1. Returns the DTB address.
+    pub fn load_dtb(&mut self, bootargs: Option<&str>) -> Result<u64, SystemBusError> {
+        let dtb = fdt::generate(&self.system_bus, 1, self.csrs[MISA as usize], bootargs);
+        let dram_end = self.system_bus.dram_base() + self.system_bus.dram_size() as u64;
+        let addr = dram_end.checked_sub(dtb.len() as u64).ok_or(SystemBusError::InvalidAddress)? & !0x7;
+        self.system_bus.write_bytes(addr, &dtb)?;
+        self.regs[10] = self.hartid();
+        self.regs[11] = addr;
+        Ok(addr)
+    }
+
+    /// Privilege an access is checked at: loads and stores made from
+    /// M-mode with MPRV set use the privilege held in MPP.
+    fn effective_mode(&self, access: AccessType) -> Mode {
+        let mstatus = self.csrs[MSTATUS as usize];
+        if access != AccessType::Fetch && mstatus & MSTATUS_MPRV != 0 {
+            Mode::from_bits((mstatus & MSTATUS_MPP) >> 11)
+        } else {
+            self.mode
+        }
+    }
+
+    fn translate(&mut self, addr: u64, access: AccessType) -> Result<u64, Exception> {
+        let mode = self.effective_mode(access);
+        mmu::translate(&mut self.system_bus, &self.csrs, mode, addr, access)
+    }
+
+    /// Translates and PMP-checks an access of `size` bits, caching the page
+    /// when it is RAM with uniform PMP permissions. Returns the DRAM offset
+    /// on a TLB hit, otherwise the physical address.
+    fn translate_cached(&mut self, addr: u64, size: usize, access: AccessType) -> Result<Result<usize, u64>, Exception> {
+        if let Some(offset) = self.tlb.lookup(addr, access, self.system_bus.generation()) {
+            return Ok(Ok(offset));
+        }
+        let paddr = self.translate(addr, access)?;
+        let mode = self.effective_mode(access);
+        if !pmp::check(&self.csrs, paddr, size as u64 / 8, access, mode) {
+            return Err(access.access_fault(addr));
+        }
+        if let Some(offset) = self.system_bus.dram_page_offset(paddr) {
+            let page = paddr & !(mmu::PAGE_SIZE - 1);
+            if pmp::check(&self.csrs, page, mmu::PAGE_SIZE, access, mode) {
+                self.tlb.insert(addr, offset, access);
+            }
+        }
+        Ok(Err(paddr))
+    }
+
+    fn fetch_parcel(&mut self, offset: u64) -> Result<u64, Exception> {
+        let addr = self.pc.wrapping_add(offset);
+        match self.translate_cached(addr, 16, AccessType::Fetch)? {
+            Ok(offset) => Ok(self.system_bus.load_dram(offset, 16)),
+            Err(paddr) => self.system_bus.load(paddr, 16)
+                .map_err(|_| Exception::InstructionAccessFault(addr)),
+        }
+    }
+
+    fn fetch(&mut self) -> Result<Inst, Exception> {
+        if self.pc & 0x1 != 0 {
+            return Err(Exception::InstructionAddressMisaligned(self.pc));
+        }
+        // Fetch in 16-bit parcels so an instruction never reads past its end
+        let low = self.fetch_parcel(0)?;
+        if low & 0x3 != 0x3 {
+            return Ok(Inst::Inst16(low as u16));
+        }
+        if low & 0x1f != 0x1f {
+            return Ok(Inst::Inst32((low | (self.fetch_parcel(2)? << 16)) as u32));
+        }
+        if low & 0x3f == 0x1f {
+            let inst = low | (self.fetch_parcel(2)? << 16) | (self.fetch_parcel(4)? << 32);
+            return Ok(Inst::Inst48(inst));
+        }
+        if low & 0x7f == 0x3f {
+            let inst = low | (self.fetch_parcel(2)? << 16) | (self.fetch_parcel(4)? << 32)
+                | (self.fetch_parcel(6)? << 48);
+            return Ok(Inst::Inst64(inst));
+        }
+        Err(Exception::IllegalInstruction(low))
+    }
+
+    fn load(&mut self, addr: u64, size: usize) -> Result<u64, Exception> {
+        if addr & (size as u64 / 8 - 1) != 0 {
+            return Err(Exception::LoadAddressMisaligned(addr));
         }
-        if inst & 0x1f < 0x1f {
-            return Ok(Inst::Inst32(inst as u32));
+        match self.translate_cached(addr, size, AccessType::Load)? {
+            Ok(offset) => Ok(self.system_bus.load_dram(offset, size)),
+            Err(paddr) => self.system_bus.load(paddr, size)
+                .map_err(|_| Exception::LoadAccessFault(addr)),
         }
-        if inst & 0x3f < 0x3f {
-            return Ok(Inst::Inst48(inst as u64));
+    }
+
+    fn store(&mut self, data: u64, addr: u64, size: usize) -> Result<(), Exception> {
+        if addr & (size as u64 / 8 - 1) != 0 {
+            return Err(Exception::StoreAddressMisaligned(addr));
         }
-        if inst ^ 0x7f == 0 {
-            return Ok(Inst::Inst64(inst as u64));
+        match self.translate_cached(addr, size, AccessType::Store)? {
+            Ok(offset) => {
+                self.system_bus.store_dram(data, offset, size);
+                Ok(())
+            }
+            Err(paddr) => self.system_bus.store(data, paddr, size)
+                .map_err(|_| Exception::StoreAccessFault(addr)),
         }
-        panic!("Not supported opcode");
     }
 }
 
 impl Processor {
-    fn execute_16(&mut self, inst: u16) -> Result<(), ProcessorError> {
-        let opcode = inst & 0x3;
-        let rd_rs1 = (((inst) >> 7) & 0xf) as usize;
-        let rs2 = ((inst >> 2) & 0x1f) as usize;
-        let funct3 = ((inst >> 13) & 0x7) as usize;
+    fn mark_fs_dirty(&mut self) {
+        self.csrs[MSTATUS as usize] |= MSTATUS_FS | MSTATUS_SD;
+    }
 
-        match opcode {
-            0x1 => {
-                match funct3 {
-                    0x1 => {
-                        // addi
-                        let imm = ((inst & 0x007c) as i32 as i64 >> 2) as u64;
-                        self.regs[rd_rs1] = self.regs[rd_rs1].wrapping_add(imm);
-                    }
-                    0x4 => {
-                        // add
-                        self.regs[rd_rs1] = self.regs[rd_rs1].wrapping_add(self.regs[rs2]);
-                    }
-                    _ => return Err(ProcessorError::NotYetImplemented),
+    // FP instructions and the FP CSRs are illegal while mstatus.FS is Off
+    fn check_fs(&self, inst: u64) -> Result<(), Exception> {
+        if self.csrs[MSTATUS as usize] & MSTATUS_FS == 0 {
+            return Err(Exception::IllegalInstruction(inst));
+        }
+        Ok(())
+    }
+
+    fn check_csr_access(&self, inst: u32, write: bool) -> Result<(), Exception> {
+        let csr = csr(inst);
+        let illegal = Exception::IllegalInstruction(inst as u64);
+        // csr[9:8] encode the lowest privilege, csr[11:10] == 3 means read-only
+        if (self.mode as u64) < (csr >> 8) & 0x3 {
+            return Err(illegal);
+        }
+        if write && (csr >> 10) & 0x3 == 0x3 {
+            return Err(illegal);
+        }
+        if matches!(csr, FFLAGS | FRM | FCSR) {
+            self.check_fs(inst as u64)?;
+        }
+        if (PMPCFG0..=PMPCFG15).contains(&csr) && csr & 0x1 != 0 {
+            return Err(illegal);
+        }
+        // The counters below M-mode are gated by mcounteren and scounteren
+        if (0xc00..0xc20).contains(&csr) {
+            let bit = 0x1 << (csr & 0x1f);
+            if (self.mode < Mode::Machine && self.csrs[MCOUNTEREN as usize] & bit == 0)
+                || (self.mode < Mode::Supervisor && self.csrs[SCOUNTEREN as usize] & bit == 0) {
+                return Err(illegal);
+            }
+        }
+        if csr == SATP && self.mode == Mode::Supervisor && self.csrs[MSTATUS as usize] & MSTATUS_TVM != 0 {
+            return Err(illegal);
+        }
+        Ok(())
+    }
+
+    fn read_csr(&self, csr: u64) -> u64 {
+        match csr {
+            FFLAGS => self.csrs[FCSR as usize] & 0x1f,
+            FRM => (self.csrs[FCSR as usize] >> 5) & 0x7,
+            FCSR => self.csrs[FCSR as usize] & 0xff,
+            SSTATUS => self.csrs[MSTATUS as usize] & SSTATUS_MASK,
+            SIE => self.csrs[MIE as usize] & self.csrs[MIDELEG as usize],
+            MIP => self.mip(),
+            SIP => self.mip() & self.csrs[MIDELEG as usize],
+            TIME => self.system_bus.clint.mtime(),
+            _ => self.csrs[csr as usize],
+        }
+    }
+
+    fn write_mstatus(&mut self, value: u64) {
+        let old = self.csrs[MSTATUS as usize];
+        // MPRV, MPP, SUM and MXR change what cached translations allow
+        self.tlb.flush();
+        let mut mstatus = (old & !MSTATUS_WRITABLE) | (value & MSTATUS_WRITABLE);
+        // MPP = 2 is reserved, keep the previous mode
+        if mstatus & MSTATUS_MPP == 0x2 << 11 {
+            mstatus = (mstatus & !MSTATUS_MPP) | (old & MSTATUS_MPP);
+        }
+        if mstatus & MSTATUS_FS == MSTATUS_FS {
+            mstatus |= MSTATUS_SD;
+        } else {
+            mstatus &= !MSTATUS_SD;
+        }
+        self.csrs[MSTATUS as usize] = mstatus;
+    }
+
+    fn write_pmpcfg(&mut self, csr: u64, value: u64) {
+        let first = (csr - PMPCFG0) as usize / 2 * 8;
+        let mut cfgs = self.csrs[csr as usize];
+        for j in 0..8 {
+            if pmp::cfg(&self.csrs, first + j) & PMP_L != 0 {
+                continue;
+            }
+            // W without R is reserved; bits 6:5 are hardwired to zero
+            let mut cfg = (value >> (8 * j)) as u8 & !0x60;
+            if cfg & PMP_R == 0 {
+                cfg &= !PMP_W;
+            }
+            cfgs = (cfgs & !(0xff << (8 * j))) | ((cfg as u64) << (8 * j));
+        }
+        self.csrs[csr as usize] = cfgs;
+        self.tlb.flush();
+    }
+
+    fn write_csr(&mut self, csr: u64, value: u64) {
+        match csr {
+            FFLAGS | FRM | FCSR => {
+                let fcsr = self.csrs[FCSR as usize];
+                self.csrs[FCSR as usize] = match csr {
+                    FFLAGS => (fcsr & !0x1f) | (value & 0x1f),
+                    FRM => (fcsr & !0xe0) | ((value & 0x7) << 5),
+                    _ => value & 0xff,
+                };
+                self.mark_fs_dirty();
+            }
+            MSTATUS => self.write_mstatus(value),
+            SSTATUS => {
+                let mstatus = self.csrs[MSTATUS as usize];
+                self.write_mstatus((mstatus & !SSTATUS_MASK) | (value & SSTATUS_MASK));
+            }
+            SIE | SIP => {
+                let reg = if csr == SIE { MIE } else { MIP } as usize;
+                // Of the pending bits S-mode may only clear or set SSIP
+                let mask = self.csrs[MIDELEG as usize] & if csr == SIP { MIP_SSIP } else { u64::MAX };
+                self.csrs[reg] = (self.csrs[reg] & !mask) | (value & mask);
+            }
+            MISA => {}
+            // MSIP, MTIP and MEIP are driven by the interrupt controllers
+            MIP => {
+                let writable = MIP_SSIP | MIP_STIP | MIP_SEIP;
+                self.csrs[MIP as usize] = (self.csrs[MIP as usize] & !writable) | (value & writable);
+            }
+            PMPCFG0..=PMPCFG15 => self.write_pmpcfg(csr, value),
+            PMPADDR0..=PMPADDR63 => {
+                let i = (csr - PMPADDR0) as usize;
+                if !pmp::addr_locked(&self.csrs, i) {
+                    self.csrs[csr as usize] = value & PMPADDR_MASK;
+                    self.tlb.flush();
+                }
+            }
+            // Unsupported translation modes leave satp unchanged
+            SATP => {
+                let mode = (value & SATP_MODE) >> 60;
+                if mode == SATP_MODE_BARE || mmu::levels(mode).is_some() {
+                    self.csrs[SATP as usize] = value;
+                    self.tlb.flush();
+                }
+            }
+            MEDELEG => self.csrs[MEDELEG as usize] = value & MEDELEG_WRITABLE,
+            MIDELEG => self.csrs[MIDELEG as usize] = value & MIDELEG_WRITABLE,
+            MIE => self.csrs[MIE as usize] = value & MIE_WRITABLE,
+            // Modes 2 and 3 are reserved
+            MTVEC | STVEC => self.csrs[csr as usize] = value & !0x2,
+            MEPC | SEPC => self.csrs[csr as usize] = value & !0x1,
+            _ => self.csrs[csr as usize] = value,
+        }
+    }
+}
+
+impl Processor {
+    fn exec_C_ADDI4SPN(&mut self, inst: u16) -> Result<(), Exception> {
+        let imm = imm_CIW(inst);
+        if imm == 0 {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        self.regs[c_rs2_p(inst)] = self.regs[2].wrapping_add(imm);
+        Ok(())
+    }
+
+    fn exec_C_FLD(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[c_rs1_p(inst)].wrapping_add(imm_CLD(inst));
+        let data = self.load(addr, 64)?;
+        self.set_freg(c_rs2_p(inst), data);
+        Ok(())
+    }
+
+    fn exec_C_LW(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[c_rs1_p(inst)].wrapping_add(imm_CLW(inst));
+        self.regs[c_rs2_p(inst)] = self.load(addr, 32)? as i32 as i64 as u64;
+        Ok(())
+    }
+
+    fn exec_C_LD(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[c_rs1_p(inst)].wrapping_add(imm_CLD(inst));
+        self.regs[c_rs2_p(inst)] = self.load(addr, 64)?;
+        Ok(())
+    }
+
+    fn exec_C_FSD(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[c_rs1_p(inst)].wrapping_add(imm_CLD(inst));
+        self.store(self.fregs[c_rs2_p(inst)], addr, 64)
+    }
+
+    fn exec_C_SW(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[c_rs1_p(inst)].wrapping_add(imm_CLW(inst));
+        self.store(self.regs[c_rs2_p(inst)], addr, 32)
+    }
+
+    fn exec_C_SD(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[c_rs1_p(inst)].wrapping_add(imm_CLD(inst));
+        self.store(self.regs[c_rs2_p(inst)], addr, 64)
+    }
+
+    fn exec_C_ADDI(&mut self, inst: u16) {
+        // C.NOP when rd is x0
+        self.regs[c_rd(inst)] = self.regs[c_rd(inst)].wrapping_add(imm_CI(inst));
+    }
+
+    fn exec_C_ADDIW(&mut self, inst: u16) -> Result<(), Exception> {
+        if c_rd(inst) == 0 {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        let res = self.regs[c_rd(inst)].wrapping_add(imm_CI(inst)) as i32;
+        self.regs[c_rd(inst)] = res as i64 as u64;
+        Ok(())
+    }
+
+    fn exec_C_LI(&mut self, inst: u16) {
+        self.regs[c_rd(inst)] = imm_CI(inst);
+    }
+
+    fn exec_C_ADDI16SP(&mut self, inst: u16) -> Result<(), Exception> {
+        let imm = imm_CI16SP(inst);
+        if imm == 0 {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        self.regs[2] = self.regs[2].wrapping_add(imm);
+        Ok(())
+    }
+
+    fn exec_C_LUI(&mut self, inst: u16) -> Result<(), Exception> {
+        let imm = imm_CI(inst) << 12;
+        if imm == 0 {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        self.regs[c_rd(inst)] = imm;
+        Ok(())
+    }
+
+    fn exec_C_MISC_ALU(&mut self, inst: u16) -> Result<(), Exception> {
+        let rd = c_rs1_p(inst);
+        let rs2 = self.regs[c_rs2_p(inst)];
+        match (inst >> 10) & 0x3 {
+            C_SRLI => self.regs[rd] >>= c_shamt(inst),
+            C_SRAI => self.regs[rd] = ((self.regs[rd] as i64) >> c_shamt(inst)) as u64,
+            C_ANDI => self.regs[rd] &= imm_CI(inst),
+            _ => match ((inst >> 12) & 0x1, (inst >> 5) & 0x3) {
+                (0, C_SUB) => self.regs[rd] = self.regs[rd].wrapping_sub(rs2),
+                (0, C_XOR) => self.regs[rd] ^= rs2,
+                (0, C_OR)  => self.regs[rd] |= rs2,
+                (0, C_AND) => self.regs[rd] &= rs2,
+                (1, C_SUBW) => self.regs[rd] = (self.regs[rd] as i32).wrapping_sub(rs2 as i32) as i64 as u64,
+                (1, C_ADDW) => self.regs[rd] = (self.regs[rd] as i32).wrapping_add(rs2 as i32) as i64 as u64,
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
+            },
+        }
+        Ok(())
+    }
+
+    fn exec_C_J(&mut self, inst: u16) {
+        self.pc = self.pc.wrapping_add(imm_CJ(inst));
+    }
+
+    fn exec_C_BEQZ(&mut self, inst: u16) {
+        if self.regs[c_rs1_p(inst)] == 0 {
+            self.pc = self.pc.wrapping_add(imm_CB(inst));
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    fn exec_C_BNEZ(&mut self, inst: u16) {
+        if self.regs[c_rs1_p(inst)] != 0 {
+            self.pc = self.pc.wrapping_add(imm_CB(inst));
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    fn exec_C_SLLI(&mut self, inst: u16) {
+        self.regs[c_rd(inst)] <<= c_shamt(inst);
+    }
+
+    fn exec_C_FLDSP(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[2].wrapping_add(imm_CLDSP(inst));
+        let data = self.load(addr, 64)?;
+        self.set_freg(c_rd(inst), data);
+        Ok(())
+    }
+
+    fn exec_C_LWSP(&mut self, inst: u16) -> Result<(), Exception> {
+        if c_rd(inst) == 0 {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        let addr = self.regs[2].wrapping_add(imm_CLWSP(inst));
+        self.regs[c_rd(inst)] = self.load(addr, 32)? as i32 as i64 as u64;
+        Ok(())
+    }
+
+    fn exec_C_LDSP(&mut self, inst: u16) -> Result<(), Exception> {
+        if c_rd(inst) == 0 {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        let addr = self.regs[2].wrapping_add(imm_CLDSP(inst));
+        self.regs[c_rd(inst)] = self.load(addr, 64)?;
+        Ok(())
+    }
+
+    fn exec_C_JR(&mut self, inst: u16) {
+        self.pc = self.regs[c_rd(inst)] & !0x1;
+    }
+
+    fn exec_C_JALR(&mut self, inst: u16) {
+        let target = self.regs[c_rd(inst)] & !0x1;
+        self.regs[1] = self.pc.wrapping_add(2);
+        self.pc = target;
+    }
+
+    fn exec_C_MV(&mut self, inst: u16) {
+        self.regs[c_rd(inst)] = self.regs[c_rs2(inst)];
+    }
+
+    fn exec_C_ADD(&mut self, inst: u16) {
+        self.regs[c_rd(inst)] = self.regs[c_rd(inst)].wrapping_add(self.regs[c_rs2(inst)]);
+    }
+
+    fn exec_C_FSDSP(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[2].wrapping_add(imm_CSDSP(inst));
+        self.store(self.fregs[c_rs2(inst)], addr, 64)
+    }
+
+    fn exec_C_SWSP(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[2].wrapping_add(imm_CSWSP(inst));
+        self.store(self.regs[c_rs2(inst)], addr, 32)
+    }
+
+    fn exec_C_SDSP(&mut self, inst: u16) -> Result<(), Exception> {
+        let addr = self.regs[2].wrapping_add(imm_CSDSP(inst));
+        self.store(self.regs[c_rs2(inst)], addr, 64)
+    }
+
+    fn execute_16(&mut self, inst: u16) -> Result<(), Exception> {
+        let quadrant = (inst & 0x3) as u64;
+        let funct3 = ((inst >> 13) & 0x7) as u64;
+        let bit12 = (inst >> 12) & 0x1;
+        let rd_rs1 = c_rd(inst);
+        let rs2 = c_rs2(inst);
+
+        if matches!((quadrant, funct3), (C0, C_FLD) | (C0, C_FSD) | (C2, C_FLDSP) | (C2, C_FSDSP)) {
+            self.check_fs(inst as u64)?;
+        }
+        match (quadrant, funct3) {
+            // Control transfers update the pc themselves
+            (C1, C_J) => {
+                self.exec_C_J(inst);
+                return Ok(());
+            }
+            (C1, C_BEQZ) => {
+                self.exec_C_BEQZ(inst);
+                return Ok(());
+            }
+            (C1, C_BNEZ) => {
+                self.exec_C_BNEZ(inst);
+                return Ok(());
+            }
+            (C2, C_JR_MV_ADD) if rs2 == 0 && rd_rs1 != 0 => {
+                match bit12 {
+                    0 => self.exec_C_JR(inst),
+                    _ => self.exec_C_JALR(inst),
                 }
+                return Ok(());
             }
-            _ => return Err(ProcessorError::NotYetImplemented),
+
+            (C0, C_ADDI4SPN) => self.exec_C_ADDI4SPN(inst)?,
+            (C0, C_FLD) => self.exec_C_FLD(inst)?,
+            (C0, C_LW)  => self.exec_C_LW(inst)?,
+            (C0, C_LD)  => self.exec_C_LD(inst)?,
+            (C0, C_FSD) => self.exec_C_FSD(inst)?,
+            (C0, C_SW)  => self.exec_C_SW(inst)?,
+            (C0, C_SD)  => self.exec_C_SD(inst)?,
+
+            (C1, C_ADDI)  => self.exec_C_ADDI(inst),
+            (C1, C_ADDIW) => self.exec_C_ADDIW(inst)?,
+            (C1, C_LI)    => self.exec_C_LI(inst),
+            (C1, C_LUI_ADDI16SP) if rd_rs1 == 2 => self.exec_C_ADDI16SP(inst)?,
+            (C1, C_LUI_ADDI16SP) => self.exec_C_LUI(inst)?,
+            (C1, C_MISC_ALU) => self.exec_C_MISC_ALU(inst)?,
+
+            (C2, C_SLLI)  => self.exec_C_SLLI(inst),
+            (C2, C_FLDSP) => self.exec_C_FLDSP(inst)?,
+            (C2, C_LWSP)  => self.exec_C_LWSP(inst)?,
+            (C2, C_LDSP)  => self.exec_C_LDSP(inst)?,
+            (C2, C_JR_MV_ADD) if bit12 == 1 && rd_rs1 == 0 && rs2 == 0 => {
+                self.exec_EBREAK(inst as u32)?
+            }
+            (C2, C_JR_MV_ADD) if rs2 != 0 => match bit12 {
+                0 => self.exec_C_MV(inst),
+                _ => self.exec_C_ADD(inst),
+            },
+            (C2, C_FSDSP) => self.exec_C_FSDSP(inst)?,
+            (C2, C_SWSP)  => self.exec_C_SWSP(inst)?,
+            (C2, C_SDSP)  => self.exec_C_SDSP(inst)?,
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
+        self.pc = self.pc.wrapping_add(2);
         Ok(())
     }
 }
@@ -104,7 +683,7 @@ impl Processor {
     }
 
     fn exec_SLL(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)].shl(self.regs[rs2(inst)]);
+        self.regs[rd(inst)] = self.regs[rs1(inst)].shl(self.regs[rs2(inst)] & 0x3f);
     }
 
     fn exec_SLT(&mut self, inst: u32) {
@@ -120,11 +699,11 @@ impl Processor {
     }
 
     fn exec_SRL(&mut self, inst: u32) {
-        self.regs[rd(inst)] = (self.regs[rs1(inst)] as i64).shr(self.regs[rs2(inst)]) as u64;
+        self.regs[rd(inst)] = self.regs[rs1(inst)].shr(self.regs[rs2(inst)] & 0x3f);
     }
 
     fn exec_SRA(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)].shr(self.regs[rs2(inst)]);
+        self.regs[rd(inst)] = (self.regs[rs1(inst)] as i64).shr(self.regs[rs2(inst)] & 0x3f) as u64;
     }
 
     fn exec_AND(&mut self, inst: u32) {
@@ -135,67 +714,179 @@ impl Processor {
         self.regs[rd(inst)] = self.regs[rs1(inst)] | self.regs[rs2(inst)];
     }
 
-    fn exec_r_type(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_MUL(&mut self, inst: u32) {
+        self.regs[rd(inst)] = self.regs[rs1(inst)].wrapping_mul(self.regs[rs2(inst)]);
+    }
+
+    fn exec_MULH(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as i64 as i128;
+        let b = self.regs[rs2(inst)] as i64 as i128;
+        self.regs[rd(inst)] = (a.wrapping_mul(b) >> 64) as u64;
+    }
+
+    fn exec_MULHSU(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as i64 as i128;
+        let b = self.regs[rs2(inst)] as i128;
+        self.regs[rd(inst)] = (a.wrapping_mul(b) >> 64) as u64;
+    }
+
+    fn exec_MULHU(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as u128;
+        let b = self.regs[rs2(inst)] as u128;
+        self.regs[rd(inst)] = (a.wrapping_mul(b) >> 64) as u64;
+    }
+
+    fn exec_DIV(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as i64;
+        let b = self.regs[rs2(inst)] as i64;
+        // x / 0 = -1, MIN / -1 = MIN (overflow)
+        self.regs[rd(inst)] = if b == 0 { u64::MAX } else { a.wrapping_div(b) as u64 };
+    }
+
+    fn exec_DIVU(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)];
+        let b = self.regs[rs2(inst)];
+        self.regs[rd(inst)] = a.checked_div(b).unwrap_or(u64::MAX);
+    }
+
+    fn exec_REM(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as i64;
+        let b = self.regs[rs2(inst)] as i64;
+        // x % 0 = x, MIN % -1 = 0 (overflow)
+        self.regs[rd(inst)] = if b == 0 { a as u64 } else { a.wrapping_rem(b) as u64 };
+    }
+
+    fn exec_REMU(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)];
+        let b = self.regs[rs2(inst)];
+        self.regs[rd(inst)] = a.checked_rem(b).unwrap_or(a);
+    }
+
+    fn exec_muldiv(&mut self, inst: u32) -> Result<(), Exception> {
+        let funct3 = funct3(inst);
+        match funct3 {
+            MUL    => self.exec_MUL(inst),
+            MULH   => self.exec_MULH(inst),
+            MULHSU => self.exec_MULHSU(inst),
+            MULHU  => self.exec_MULHU(inst),
+            DIV    => self.exec_DIV(inst),
+            DIVU   => self.exec_DIVU(inst),
+            REM    => self.exec_REM(inst),
+            REMU   => self.exec_REMU(inst),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        }
+        Ok(())
+    }
+
+    fn exec_r_type(&mut self, inst: u32) -> Result<(), Exception> {
         let funct7 = funct7(inst);
         let funct3 = funct3(inst);
-        match funct7 {
-            ADD_FUNCT3 => match funct3 {
+        if funct7 == MULDIV {
+            return self.exec_muldiv(inst);
+        }
+        match funct3 {
+            ADD_FUNCT3 => match funct7 {
                 ADD => self.exec_ADD(inst),
                 SUB => self.exec_SUB(inst),
-                _ => return Err(ProcessorError::NotYetImplemented),
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
             },
-            SLL => self.exec_SLL(inst),
-            SLT => self.exec_SLT(inst),
-            SLTU => self.exec_SLTU(inst),
-            XOR => self.exec_XOR(inst),
-            SRL_FUNCT3 => match funct3 {
+            SLL if funct7 == 0 => self.exec_SLL(inst),
+            SLT if funct7 == 0 => self.exec_SLT(inst),
+            SLTU if funct7 == 0 => self.exec_SLTU(inst),
+            XOR if funct7 == 0 => self.exec_XOR(inst),
+            SRL_FUNCT3 => match funct7 {
                 SRL => self.exec_SRL(inst),
                 SRA => self.exec_SRA(inst),   
-                _ => return Err(ProcessorError::NotYetImplemented),
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
             }
-            OR  => self.exec_OR(inst),
-            AND => self.exec_AND(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+            OR if funct7 == 0 => self.exec_OR(inst),
+            AND if funct7 == 0 => self.exec_AND(inst),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
     fn exec_ADDW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)].wrapping_add(self.regs[rs2(inst)]);
+        let res = (self.regs[rs1(inst)] as i32).wrapping_add(self.regs[rs2(inst)] as i32);
+        self.regs[rd(inst)] = res as i64 as u64;
     }
 
     fn exec_SUBW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)].wrapping_sub(self.regs[rs2(inst)]);
+        let res = (self.regs[rs1(inst)] as i32).wrapping_sub(self.regs[rs2(inst)] as i32);
+        self.regs[rd(inst)] = res as i64 as u64;
     }
 
     fn exec_SLLW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)].shl(self.regs[rs2(inst)]);
+        let res = (self.regs[rs1(inst)] as u32).shl(self.regs[rs2(inst)] & 0x1f);
+        self.regs[rd(inst)] = res as i32 as i64 as u64;
     }
 
     fn exec_SRLW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = (self.regs[rs1(inst)] as i64).shr(self.regs[rs2(inst)]) as u64;
+        let res = (self.regs[rs1(inst)] as u32).shr(self.regs[rs2(inst)] & 0x1f);
+        self.regs[rd(inst)] = res as i32 as i64 as u64;
     }
 
     fn exec_SRAW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)].shr(self.regs[rs2(inst)]);
+        let res = (self.regs[rs1(inst)] as i32).shr(self.regs[rs2(inst)] & 0x1f);
+        self.regs[rd(inst)] = res as i64 as u64;
+    }
+
+    fn exec_MULW(&mut self, inst: u32) {
+        let res = (self.regs[rs1(inst)] as i32).wrapping_mul(self.regs[rs2(inst)] as i32);
+        self.regs[rd(inst)] = res as i64 as u64;
+    }
+
+    fn exec_DIVW(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as i32;
+        let b = self.regs[rs2(inst)] as i32;
+        let res = if b == 0 { -1 } else { a.wrapping_div(b) };
+        self.regs[rd(inst)] = res as i64 as u64;
+    }
+
+    fn exec_DIVUW(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as u32;
+        let b = self.regs[rs2(inst)] as u32;
+        let res = a.checked_div(b).unwrap_or(u32::MAX);
+        self.regs[rd(inst)] = res as i32 as i64 as u64;
+    }
+
+    fn exec_REMW(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as i32;
+        let b = self.regs[rs2(inst)] as i32;
+        let res = if b == 0 { a } else { a.wrapping_rem(b) };
+        self.regs[rd(inst)] = res as i64 as u64;
+    }
+
+    fn exec_REMUW(&mut self, inst: u32) {
+        let a = self.regs[rs1(inst)] as u32;
+        let b = self.regs[rs2(inst)] as u32;
+        let res = a.checked_rem(b).unwrap_or(a);
+        self.regs[rd(inst)] = res as i32 as i64 as u64;
     }
 
-    fn exec_r_type_64(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_r_type_64(&mut self, inst: u32) -> Result<(), Exception> {
         let funct7 = funct7(inst);
         let funct3 = funct3(inst);
         match funct3 {
             ADDSUB => match funct7 {
                 ADDW => self.exec_ADDW(inst),
+                MULW => self.exec_MULW(inst),
                 SUBW => self.exec_SUBW(inst),
-                _ => return Err(ProcessorError::NotYetImplemented),
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
             }
             SLLW => self.exec_SLLW(inst),
             SRW => match funct7 {
                 SRLW => self.exec_SRLW(inst),
+                DIVUW => self.exec_DIVUW(inst),
                 SRAW => self.exec_SRAW(inst),
-                _ => return Err(ProcessorError::NotYetImplemented),
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
+            }
+            DIVW | REMW | REMUW if funct7 == MULDIV => match funct3 {
+                DIVW => self.exec_DIVW(inst),
+                REMW => self.exec_REMW(inst),
+                _ => self.exec_REMUW(inst),
             }
-            _ => return Err(ProcessorError::NotYetImplemented),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
@@ -230,11 +921,11 @@ impl Processor {
     }
 
     fn exec_SRLI(&mut self, inst: u32) {
-        self.regs[rd(inst)] = ((self.regs[rs1(inst)] as i64) >> imm_I(inst)) as u64;
+        self.regs[rd(inst)] = self.regs[rs1(inst)] >> shamt(inst);
     }
 
     fn exec_SRAI(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)] >> imm_I(inst);
+        self.regs[rd(inst)] = ((self.regs[rs1(inst)] as i64) >> shamt(inst)) as u64;
     }
 
     fn exec_ANDI(&mut self, inst: u32) {
@@ -245,7 +936,7 @@ impl Processor {
         self.regs[rd(inst)] = self.regs[rs1(inst)] | imm_I(inst);
     }
 
-    fn exec_i_type(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_i_type(&mut self, inst: u32) -> Result<(), Exception> {
         let funct7 = funct7(inst);
         let funct3 = funct3(inst);
         match funct3 {
@@ -254,36 +945,41 @@ impl Processor {
             SLTI => self.exec_SLTI(inst),
             SLTIU => self.exec_SLTIU(inst),
             XORI => self.exec_XORI(inst),
-            SRI_FUNCT3 => match funct7 {
+            // shamt[5] lives in the lowest funct7 bit on RV64
+            SRI_FUNCT3 => match funct7 & !0x1 {
                 SRLI => self.exec_SRLI(inst),
                 SRAI => self.exec_SRAI(inst),   
-                _ => return Err(ProcessorError::NotYetImplemented),
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
             }
             ORI  => self.exec_ORI(inst),
             ANDI => self.exec_ANDI(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
     fn exec_ADDIW(&mut self, inst: u32) {
         let imm = imm_I(inst);
-        self.regs[rd(inst)] = self.regs[rs1(inst)].wrapping_add(imm);
+        let res = self.regs[rs1(inst)].wrapping_add(imm) as i32;
+        self.regs[rd(inst)] = res as i64 as u64;
     }
 
     fn exec_SLLIW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)] << shamt(inst);
+        let res = (self.regs[rs1(inst)] as u32) << shamt_W(inst);
+        self.regs[rd(inst)] = res as i32 as i64 as u64;
     }
 
     fn exec_SRLIW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = ((self.regs[rs1(inst)] as i64) >> imm_I(inst)) as u64;
+        let res = (self.regs[rs1(inst)] as u32) >> shamt_W(inst);
+        self.regs[rd(inst)] = res as i32 as i64 as u64;
     }
 
     fn exec_SRAIW(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.regs[rs1(inst)] >> imm_I(inst);
+        let res = (self.regs[rs1(inst)] as i32) >> shamt_W(inst);
+        self.regs[rd(inst)] = res as i64 as u64;
     }
 
-    fn exec_i_type_64(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_i_type_64(&mut self, inst: u32) -> Result<(), Exception> {
         let funct7 = funct7(inst);
         let funct3 = funct3(inst);
         match funct3 {
@@ -292,9 +988,9 @@ impl Processor {
             SRIW => match funct7 {
                 SRLIW => self.exec_SRLIW(inst),
                 SRAIW => self.exec_SRAIW(inst),
-                _ => return Err(ProcessorError::NotYetImplemented),
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
             }
-            _ => return Err(ProcessorError::NotYetImplemented),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
@@ -304,66 +1000,63 @@ impl Processor {
     }
 
     fn exec_AUIPC(&mut self, inst: u32) {
-        self.regs[rd(inst)] = imm_U(inst) + self.pc;
+        self.regs[rd(inst)] = imm_U(inst).wrapping_add(self.pc);
     }
 
-    fn exec_u_type(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_u_type(&mut self, inst: u32) -> Result<(), Exception> {
         let opcode = op(inst);
         match opcode {
             LUI => self.exec_LUI(inst),
             AUIPC => self.exec_AUIPC(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
     fn exec_JAL(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.pc + 4;
-        self.pc = self.pc + imm_J(inst);
+        self.regs[rd(inst)] = self.pc.wrapping_add(4);
+        self.pc = self.pc.wrapping_add(imm_J(inst));
     }
 
     fn exec_JALR(&mut self, inst: u32) {
-        self.regs[rd(inst)] = self.pc + 4;
-        self.pc = self.regs[rs1(inst)] + imm_I(inst);
+        let target = self.regs[rs1(inst)].wrapping_add(imm_I(inst)) & !0x1;
+        self.regs[rd(inst)] = self.pc.wrapping_add(4);
+        self.pc = target;
     }
 
-    fn exec_BEQ(&mut self, inst: u32) {
-        if self.regs[rs1(inst)] == self.regs[rs2(inst)] {
-            self.pc = self.pc + imm_B(inst);
+    fn branch(&mut self, inst: u32, taken: bool) {
+        if taken {
+            self.pc = self.pc.wrapping_add(imm_B(inst));
+        } else {
+            self.pc = self.pc.wrapping_add(4);
         }
     }
 
+    fn exec_BEQ(&mut self, inst: u32) {
+        self.branch(inst, self.regs[rs1(inst)] == self.regs[rs2(inst)]);
+    }
+
     fn exec_BNE(&mut self, inst: u32) {
-        if self.regs[rs1(inst)] != self.regs[rs2(inst)] {
-            self.pc = self.pc + imm_B(inst);
-        }
+        self.branch(inst, self.regs[rs1(inst)] != self.regs[rs2(inst)]);
     }
 
     fn exec_BLT(&mut self, inst: u32) {
-        if (self.regs[rs1(inst)] as i64) < (self.regs[rs2(inst)] as i64) {
-            self.pc = self.pc + imm_B(inst);
-        }
+        self.branch(inst, (self.regs[rs1(inst)] as i64) < (self.regs[rs2(inst)] as i64));
     }
 
     fn exec_BGE(&mut self, inst: u32) {
-        if (self.regs[rs1(inst)] as i64) >= (self.regs[rs2(inst)] as i64) {
-            self.pc = self.pc + imm_B(inst);
-        }
+        self.branch(inst, (self.regs[rs1(inst)] as i64) >= (self.regs[rs2(inst)] as i64));
     }
 
     fn exec_BLTU(&mut self, inst: u32) {
-        if self.regs[rs1(inst)] < self.regs[rs2(inst)] {
-            self.pc = self.pc + imm_B(inst);
-        }
+        self.branch(inst, self.regs[rs1(inst)] < self.regs[rs2(inst)]);
     }
 
     fn exec_BGEU(&mut self, inst: u32) {
-        if self.regs[rs1(inst)] >= self.regs[rs2(inst)] {
-            self.pc = self.pc + imm_B(inst);
-        }
+        self.branch(inst, self.regs[rs1(inst)] >= self.regs[rs2(inst)]);
     }
 
-    fn exec_b_type(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_b_type(&mut self, inst: u32) -> Result<(), Exception> {
         let opcode = op(inst);
         let funct3 = funct3(inst);
         match funct3 {
@@ -373,218 +1066,582 @@ impl Processor {
             BGE  => self.exec_BGE(inst),
             BLTU => self.exec_BLTU(inst),
             BGEU => self.exec_BGEU(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
-    fn exec_LB(&mut self, inst: u32) {
+    fn load_addr(&self, inst: u32) -> u64 {
+        self.regs[rs1(inst)].wrapping_add(imm_I(inst))
+    }
+
+    fn exec_LB(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 8;
-        self.regs[rd(inst)] = self.regs[rd(inst)] & (!0xff) 
-            | self.system_bus.load(rs1(inst) as u64, SZ).expect("Read bus error");
+        let data = self.load(self.load_addr(inst), SZ)?;
+        self.regs[rd(inst)] = data as i8 as i64 as u64;
+        Ok(())
     }
 
-    fn exec_LH(&mut self, inst: u32) {
+    fn exec_LH(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 16;
-        self.regs[rd(inst)] = self.regs[rd(inst)] & (!0xffff)
-            | self.system_bus.load(rs1(inst) as u64, SZ).expect("Read bus error");
+        let data = self.load(self.load_addr(inst), SZ)?;
+        self.regs[rd(inst)] = data as i16 as i64 as u64;
+        Ok(())
     }
 
-    fn exec_LW(&mut self, inst: u32) {
+    fn exec_LW(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 32;
-        self.regs[rd(inst)] = self.regs[rd(inst)] & (!0xffff_ffff)
-            | self.system_bus.load(rs1(inst) as u64, SZ).expect("Read bus error");
+        let data = self.load(self.load_addr(inst), SZ)?;
+        self.regs[rd(inst)] = data as i32 as i64 as u64;
+        Ok(())
     }
 
-    fn exec_LD(&mut self, inst: u32) {
+    fn exec_LD(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 64;
-        self.regs[rd(inst)] = self.regs[rd(inst)]
-            | self.system_bus.load(rs1(inst) as u64, SZ).expect("Read bus error");
+        self.regs[rd(inst)] = self.load(self.load_addr(inst), SZ)?;
+        Ok(())
     }
 
-    fn exec_LBU(&mut self, inst: u32) {
+    fn exec_LBU(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 8;
-        self.regs[rd(inst)] = self.regs[rd(inst)] & (!0xff) 
-            | self.system_bus.load(rs1(inst) as u64, SZ).expect("Read bus error");
+        self.regs[rd(inst)] = self.load(self.load_addr(inst), SZ)?;
+        Ok(())
     }
 
-    fn exec_LHU(&mut self, inst: u32) {
+    fn exec_LHU(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 16;
-        self.regs[rd(inst)] = self.regs[rd(inst)] & (!0xffff)
-            | self.system_bus.load(rs1(inst) as u64, SZ).expect("Read bus error");
+        self.regs[rd(inst)] = self.load(self.load_addr(inst), SZ)?;
+        Ok(())
     }
 
-    fn exec_LWU(&mut self, inst: u32) {
+    fn exec_LWU(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 32;
-        self.regs[rd(inst)] = self.regs[rd(inst)] & (!0xffff_ffff)
-            | self.system_bus.load(rs1(inst) as u64, SZ).expect("Read bus error");
+        self.regs[rd(inst)] = self.load(self.load_addr(inst), SZ)?;
+        Ok(())
     }
 
-    fn exec_load(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_load(&mut self, inst: u32) -> Result<(), Exception> {
         let funct3 = funct3(inst);
         match funct3 {
-            LB => self.exec_LB(inst),
-            LH => self.exec_LH(inst),
-            LW => self.exec_LW(inst),
-            LD => self.exec_LD(inst),
-            LBU => self.exec_LBU(inst),
-            LHU => self.exec_LHU(inst),
-            LWU => self.exec_LWU(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+            LB => self.exec_LB(inst)?,
+            LH => self.exec_LH(inst)?,
+            LW => self.exec_LW(inst)?,
+            LD => self.exec_LD(inst)?,
+            LBU => self.exec_LBU(inst)?,
+            LHU => self.exec_LHU(inst)?,
+            LWU => self.exec_LWU(inst)?,
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
-    fn exec_SB(&mut self, inst: u32) {
+    fn exec_SB(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 8;
         let offset = imm_S(inst);
-        let store_addr = offset + self.regs[rs1(inst)];
-        let data = rs2(inst);
-        self.system_bus.store(data as u64, store_addr, SZ).expect("Read bus error");
+        let store_addr = offset.wrapping_add(self.regs[rs1(inst)]);
+        let data = self.regs[rs2(inst)];
+        self.store(data, store_addr, SZ)
     }
 
-    fn exec_SH(&mut self, inst: u32) {
+    fn exec_SH(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 16;
         let offset = imm_S(inst);
-        let store_addr = offset + self.regs[rs1(inst)];
-        let data = rs2(inst);
-        self.system_bus.store(data as u64, store_addr, SZ).expect("Read bus error");
+        let store_addr = offset.wrapping_add(self.regs[rs1(inst)]);
+        let data = self.regs[rs2(inst)];
+        self.store(data, store_addr, SZ)
     }
 
-    fn exec_SW(&mut self, inst: u32) {
+    fn exec_SW(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 32;
         let offset = imm_S(inst);
-        let store_addr = offset + self.regs[rs1(inst)];
-        let data = rs2(inst);
-        self.system_bus.store(data as u64, store_addr, SZ).expect("Read bus error");
+        let store_addr = offset.wrapping_add(self.regs[rs1(inst)]);
+        let data = self.regs[rs2(inst)];
+        self.store(data, store_addr, SZ)
     }
 
-    fn exec_SD(&mut self, inst: u32) {
+    fn exec_SD(&mut self, inst: u32) -> Result<(), Exception> {
         const SZ: usize = 64;
         let offset = imm_S(inst);
-        let store_addr = offset + self.regs[rs1(inst)];
-        let data = rs2(inst);
-        self.system_bus.store(data as u64, store_addr, SZ).expect("Read bus error");
+        let store_addr = offset.wrapping_add(self.regs[rs1(inst)]);
+        let data = self.regs[rs2(inst)];
+        self.store(data, store_addr, SZ)
     }
 
-    fn exec_store(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_store(&mut self, inst: u32) -> Result<(), Exception> {
         let funct3 = funct3(inst);
         match funct3 {
-            SB => self.exec_SB(inst),
-            SH => self.exec_SH(inst),
-            SW => self.exec_SW(inst),
-            SD => self.exec_SD(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+            SB => self.exec_SB(inst)?,
+            SH => self.exec_SH(inst)?,
+            SW => self.exec_SW(inst)?,
+            SD => self.exec_SD(inst)?,
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
-    fn exec_ECALL(&mut self, inst: u32) {
-        todo!()
+    fn exec_ECALL(&mut self, inst: u32) -> Result<(), Exception> {
+        Err(match self.mode {
+            Mode::User => Exception::EnvironmentCallFromUMode,
+            Mode::Supervisor => Exception::EnvironmentCallFromSMode,
+            Mode::Machine => Exception::EnvironmentCallFromMMode,
+        })
     }
 
-    fn exec_EBREAK(&mut self, inst: u32) {
-        todo!()
+    fn exec_EBREAK(&mut self, inst: u32) -> Result<(), Exception> {
+        Err(Exception::Breakpoint(self.pc))
     }
 
-    fn exec_SRET(&mut self, inst: u32) {
-        todo!()
+    fn exec_WFI(&mut self, inst: u32) -> Result<(), Exception> {
+        // No time limit is given to lower modes, so TW traps right away
+        if self.mode == Mode::User
+            || (self.mode == Mode::Supervisor && self.csrs[MSTATUS as usize] & MSTATUS_TW != 0) {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        self.wfi = true;
+        Ok(())
     }
 
-    fn exec_MRET(&mut self, inst: u32) {
-        todo!()
+    fn exec_SFENCE_VMA(&mut self, inst: u32) -> Result<(), Exception> {
+        if self.mode == Mode::User
+            || (self.mode == Mode::Supervisor && self.csrs[MSTATUS as usize] & MSTATUS_TVM != 0) {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        self.tlb.flush();
+        Ok(())
     }
 
-    fn exec_CSRRW(&mut self, inst: u32) {
-        let csr = csr(inst) as usize;
-        let tmp = self.csrs[csr];
-        self.csrs[csr] = self.regs[rs1(inst)];
-        self.regs[rd(inst)] = tmp;
+    fn exec_SRET(&mut self, inst: u32) -> Result<(), Exception> {
+        let mstatus = self.csrs[MSTATUS as usize];
+        if self.mode < Mode::Supervisor
+            || (self.mode == Mode::Supervisor && mstatus & MSTATUS_TSR != 0) {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        let spp = Mode::from_bits((mstatus & MSTATUS_SPP) >> 8);
+        let spie = (mstatus & MSTATUS_SPIE) >> 4;
+        // SIE <- SPIE, SPIE <- 1, SPP <- U
+        let mut mstatus = (mstatus & !(MSTATUS_SIE | MSTATUS_SPP)) | spie | MSTATUS_SPIE;
+        if spp != Mode::Machine {
+            mstatus &= !MSTATUS_MPRV;
+        }
+        self.set_trap_mstatus(mstatus);
+        self.set_mode(spp);
+        self.pc = self.csrs[SEPC as usize];
+        Ok(())
     }
 
-    fn exec_CSRRS(&mut self, inst: u32) {
-        let csr = csr(inst) as usize;
-        let tmp = self.csrs[csr];
-        self.csrs[csr] = self.csrs[csr] | self.regs[rs1(inst)];
-        self.regs[rd(inst)] = tmp;
+    fn exec_MRET(&mut self, inst: u32) -> Result<(), Exception> {
+        if self.mode != Mode::Machine {
+            return Err(Exception::IllegalInstruction(inst as u64));
+        }
+        let mstatus = self.csrs[MSTATUS as usize];
+        let mpp = Mode::from_bits((mstatus & MSTATUS_MPP) >> 11);
+        let mpie = (mstatus & MSTATUS_MPIE) >> 4;
+        // MIE <- MPIE, MPIE <- 1, MPP <- U
+        let mut mstatus = (mstatus & !(MSTATUS_MIE | MSTATUS_MPP)) | mpie | MSTATUS_MPIE;
+        if mpp != Mode::Machine {
+            mstatus &= !MSTATUS_MPRV;
+        }
+        self.set_trap_mstatus(mstatus);
+        self.set_mode(mpp);
+        self.pc = self.csrs[MEPC as usize];
+        Ok(())
     }
 
-    fn exec_CSRRC(&mut self, inst: u32) {
-        let csr = csr(inst) as usize;
-        let tmp = self.csrs[csr];
-        self.csrs[csr] = self.csrs[csr] & (!self.regs[rs1(inst)]);
+    fn exec_CSRRW(&mut self, inst: u32) -> Result<(), Exception> {
+        self.check_csr_access(inst, true)?;
+        let csr = csr(inst);
+        let tmp = self.read_csr(csr);
+        self.write_csr(csr, self.regs[rs1(inst)]);
         self.regs[rd(inst)] = tmp;
+        Ok(())
     }
 
-    fn exec_CSRRWI(&mut self, inst: u32) {
-        let csr = csr(inst) as usize;
-        let tmp = self.csrs[csr];
-        self.csrs[csr] = rs1(inst) as u64;
+    fn exec_CSRRS(&mut self, inst: u32) -> Result<(), Exception> {
+        self.check_csr_access(inst, rs1(inst) != 0)?;
+        let csr = csr(inst);
+        let tmp = self.read_csr(csr);
+        if rs1(inst) != 0 {
+            self.write_csr(csr, tmp | self.regs[rs1(inst)]);
+        }
         self.regs[rd(inst)] = tmp;
+        Ok(())
     }
 
-    fn exec_CSRRSI(&mut self, inst: u32) {
-        let csr = csr(inst) as usize;
-        let tmp = self.csrs[csr];
-        self.csrs[csr] = self.csrs[csr] | (rs1(inst) as u64);
+    fn exec_CSRRC(&mut self, inst: u32) -> Result<(), Exception> {
+        self.check_csr_access(inst, rs1(inst) != 0)?;
+        let csr = csr(inst);
+        let tmp = self.read_csr(csr);
+        if rs1(inst) != 0 {
+            self.write_csr(csr, tmp & !self.regs[rs1(inst)]);
+        }
         self.regs[rd(inst)] = tmp;
+        Ok(())
     }
 
-    fn exec_CSRRCI(&mut self, inst: u32) {
-        let csr = csr(inst) as usize;
-        let tmp = self.csrs[csr];
-        self.csrs[csr] = self.csrs[csr] & (!(rs1(inst) as u64));
+    fn exec_CSRRWI(&mut self, inst: u32) -> Result<(), Exception> {
+        self.check_csr_access(inst, true)?;
+        let csr = csr(inst);
+        let tmp = self.read_csr(csr);
+        self.write_csr(csr, rs1(inst) as u64);
         self.regs[rd(inst)] = tmp;
+        Ok(())
     }
 
-    fn exec_system(&mut self, inst: u32) -> Result<(), ProcessorError> {
-        let funct3 = funct3(inst);
-        let funct7 = funct7(inst);
-        match funct3 {
-            ECALLBREAK => match funct7 {
-                ECALL  => self.exec_ECALL(inst),
-                EBREAK => self.exec_EBREAK(inst),
-                SRET   => self.exec_SRET(inst),
-                MRET   => self.exec_MRET(inst),
-                _ => return Err(ProcessorError::NotYetImplemented),
-            },
-            CSRRW => self.exec_CSRRW(inst),
-            CSRRS => self.exec_CSRRS(inst),
-            CSRRC => self.exec_CSRRC(inst),
-            CSRRWI => self.exec_CSRRWI(inst),
-            CSRRSI => self.exec_CSRRSI(inst),
-            CSRRCI => self.exec_CSRRCI(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+    fn exec_CSRRSI(&mut self, inst: u32) -> Result<(), Exception> {
+        self.check_csr_access(inst, rs1(inst) != 0)?;
+        let csr = csr(inst);
+        let tmp = self.read_csr(csr);
+        if rs1(inst) != 0 {
+            self.write_csr(csr, tmp | rs1(inst) as u64);
+        }
+        self.regs[rd(inst)] = tmp;
+        Ok(())
+    }
+
+    fn exec_CSRRCI(&mut self, inst: u32) -> Result<(), Exception> {
+        self.check_csr_access(inst, rs1(inst) != 0)?;
+        let csr = csr(inst);
+        let tmp = self.read_csr(csr);
+        if rs1(inst) != 0 {
+            self.write_csr(csr, tmp & !(rs1(inst) as u64));
+        }
+        self.regs[rd(inst)] = tmp;
+        Ok(())
+    }
+
+    fn exec_system(&mut self, inst: u32) -> Result<(), Exception> {
+        let funct3 = funct3(inst);
+        match funct3 {
+            PRIV if funct7(inst) == SFENCE_VMA && rd(inst) == 0 => self.exec_SFENCE_VMA(inst)?,
+            PRIV => match csr(inst) {
+                ECALL  => self.exec_ECALL(inst)?,
+                EBREAK => self.exec_EBREAK(inst)?,
+                WFI    => self.exec_WFI(inst)?,
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
+            },
+            CSRRW => self.exec_CSRRW(inst)?,
+            CSRRS => self.exec_CSRRS(inst)?,
+            CSRRC => self.exec_CSRRC(inst)?,
+            CSRRWI => self.exec_CSRRWI(inst)?,
+            CSRRSI => self.exec_CSRRSI(inst)?,
+            CSRRCI => self.exec_CSRRCI(inst)?,
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
     fn exec_FENCE(&mut self, inst: u32) {
-        todo!()
+        // Single in-order hart: memory is always coherent
     }
 
     fn exec_FENCE_I(&mut self, inst: u32) {
-        todo!()
+        // No instruction cache to flush
     }
 
-    fn exec_fence(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_fence(&mut self, inst: u32) -> Result<(), Exception> {
         let funct3 = funct3(inst);
         match funct3 {
             FENCE_FUNCT3 => self.exec_FENCE(inst),
             FENCE_I_FUNCT3 => self.exec_FENCE_I(inst),
-            _ => return Err(ProcessorError::NotYetImplemented),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        }
+        Ok(())
+    }
+
+    fn rounding_mode(&self, inst: u32) -> Result<Round, Exception> {
+        let rm = match funct3(inst) {
+            RM_DYN => self.read_csr(FRM),
+            rm => rm,
+        };
+        rounding_mode(rm).ok_or(Exception::IllegalInstruction(inst as u64))
+    }
+
+    fn set_fflags(&mut self, flags: u64) {
+        if flags != 0 {
+            self.csrs[FCSR as usize] |= flags;
+            self.mark_fs_dirty();
+        }
+    }
+
+    fn set_freg(&mut self, reg: usize, value: u64) {
+        self.fregs[reg] = value;
+        self.mark_fs_dirty();
+    }
+
+    fn set_freg_result<F: FpFormat>(&mut self, reg: usize, res: StatusAnd<F>) {
+        self.set_fflags(fflags(res.status));
+        self.set_freg(reg, canonical(res.value));
+    }
+
+    fn exec_FLW(&mut self, inst: u32) -> Result<(), Exception> {
+        let data = self.load(self.load_addr(inst), 32)?;
+        self.set_freg(rd(inst), Single::box_bits(data));
+        Ok(())
+    }
+
+    fn exec_FLD(&mut self, inst: u32) -> Result<(), Exception> {
+        let data = self.load(self.load_addr(inst), 64)?;
+        self.set_freg(rd(inst), data);
+        Ok(())
+    }
+
+    fn exec_load_fp(&mut self, inst: u32) -> Result<(), Exception> {
+        match funct3(inst) {
+            FLW => self.exec_FLW(inst),
+            FLD => self.exec_FLD(inst),
+            _ => Err(Exception::IllegalInstruction(inst as u64)),
+        }
+    }
+
+    fn exec_FSW(&mut self, inst: u32) -> Result<(), Exception> {
+        let addr = self.regs[rs1(inst)].wrapping_add(imm_S(inst));
+        self.store(self.fregs[rs2(inst)] & 0xffff_ffff, addr, 32)
+    }
+
+    fn exec_FSD(&mut self, inst: u32) -> Result<(), Exception> {
+        let addr = self.regs[rs1(inst)].wrapping_add(imm_S(inst));
+        self.store(self.fregs[rs2(inst)], addr, 64)
+    }
+
+    fn exec_store_fp(&mut self, inst: u32) -> Result<(), Exception> {
+        match funct3(inst) {
+            FSW => self.exec_FSW(inst),
+            FSD => self.exec_FSD(inst),
+            _ => Err(Exception::IllegalInstruction(inst as u64)),
+        }
+    }
+
+    fn exec_fma<F: FpFormat>(&mut self, inst: u32) -> Result<(), Exception> {
+        let round = self.rounding_mode(inst)?;
+        let a = F::unbox(self.fregs[rs1(inst)]);
+        let b = F::unbox(self.fregs[rs2(inst)]);
+        let c = F::unbox(self.fregs[rs3(inst)]);
+        let res = match op(inst) {
+            FMADD  => a.mul_add_r(b, c, round),
+            FMSUB  => a.mul_add_r(b, -c, round),
+            FNMSUB => (-a).mul_add_r(b, c, round),
+            FNMADD => (-a).mul_add_r(b, -c, round),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        };
+        self.set_freg_result(rd(inst), res);
+        Ok(())
+    }
+
+    fn exec_FSGNJ<F: FpFormat>(&mut self, inst: u32) -> Result<(), Exception> {
+        let a = F::unbox_bits(self.fregs[rs1(inst)]);
+        let b = F::unbox_bits(self.fregs[rs2(inst)]);
+        let sign = match funct3(inst) {
+            FSGNJ_J => b,
+            FSGNJ_N => !b,
+            FSGNJ_X => a ^ b,
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        } & F::sign_bit();
+        self.set_freg(rd(inst), F::box_bits((a & !F::sign_bit()) | sign));
+        Ok(())
+    }
+
+    fn exec_FCMP<F: FpFormat>(&mut self, inst: u32) -> Result<(), Exception> {
+        let a = F::unbox(self.fregs[rs1(inst)]);
+        let b = F::unbox(self.fregs[rs2(inst)]);
+        let res = match funct3(inst) {
+            FLE => fle(a, b),
+            FLT => flt(a, b),
+            FEQ => feq(a, b),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        };
+        self.set_fflags(fflags(res.status));
+        self.regs[rd(inst)] = res.value as u64;
+        Ok(())
+    }
+
+    fn exec_FCVT_INT_FMT<F: FpFormat>(&mut self, inst: u32) -> Result<(), Exception> {
+        let round = self.rounding_mode(inst)?;
+        let a = F::unbox(self.fregs[rs1(inst)]);
+        let res = match rs2(inst) as u64 {
+            FCVT_W  => to_int(a, 32, true, round),
+            FCVT_WU => to_int(a, 32, false, round),
+            FCVT_L  => to_int(a, 64, true, round),
+            FCVT_LU => to_int(a, 64, false, round),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        };
+        self.set_fflags(fflags(res.status));
+        self.regs[rd(inst)] = res.value;
+        Ok(())
+    }
+
+    fn exec_FCVT_FMT_INT<F: FpFormat>(&mut self, inst: u32) -> Result<(), Exception> {
+        let round = self.rounding_mode(inst)?;
+        let a = self.regs[rs1(inst)];
+        let res: StatusAnd<F> = match rs2(inst) as u64 {
+            FCVT_W  => from_int(a, 32, true, round),
+            FCVT_WU => from_int(a, 32, false, round),
+            FCVT_L  => from_int(a, 64, true, round),
+            FCVT_LU => from_int(a, 64, false, round),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        };
+        self.set_freg_result(rd(inst), res);
+        Ok(())
+    }
+
+    /// FCVT between formats, `F` is the destination format and `S` the source.
+    fn exec_FCVT_FMT_FMT<F: FpFormat, S: FpFormat + FloatConvert<F>>(&mut self, inst: u32) -> Result<(), Exception> {
+        let round = self.rounding_mode(inst)?;
+        let a = S::unbox(self.fregs[rs1(inst)]);
+        let mut loses_info = false;
+        let res = a.convert_r(round, &mut loses_info);
+        self.set_freg_result(rd(inst), res);
+        Ok(())
+    }
+
+    fn exec_FMV_X_FCLASS<F: FpFormat>(&mut self, inst: u32) -> Result<(), Exception> {
+        let reg = self.fregs[rs1(inst)];
+        self.regs[rd(inst)] = match funct3(inst) {
+            // FMV.X.W moves the raw low bits, boxed or not
+            FMV_X if F::BITS == 32 => reg as u32 as i32 as u64,
+            FMV_X => reg,
+            FCLASS => classify(F::unbox(reg)),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        };
+        Ok(())
+    }
+
+    fn exec_op_fp<F: FpFormat>(&mut self, inst: u32) -> Result<(), Exception> {
+        let a = F::unbox(self.fregs[rs1(inst)]);
+        let b = F::unbox(self.fregs[rs2(inst)]);
+        match funct5(inst) {
+            FADD | FSUB | FMUL | FDIV | FSQRT => {
+                let round = self.rounding_mode(inst)?;
+                let res = match funct5(inst) {
+                    FADD => a.add_r(b, round),
+                    FSUB => a.sub_r(b, round),
+                    FMUL => a.mul_r(b, round),
+                    FDIV => a.div_r(b, round),
+                    _ => sqrt(a, round),
+                };
+                self.set_freg_result(rd(inst), res);
+            }
+            FSGNJ => self.exec_FSGNJ::<F>(inst)?,
+            FMINMAX => match funct3(inst) {
+                FMIN => self.set_freg_result(rd(inst), min_max(a, b, false)),
+                FMAX => self.set_freg_result(rd(inst), min_max(a, b, true)),
+                _ => return Err(Exception::IllegalInstruction(inst as u64)),
+            },
+            FCMP => self.exec_FCMP::<F>(inst)?,
+            FCVT_INT_FMT => self.exec_FCVT_INT_FMT::<F>(inst)?,
+            FCVT_FMT_INT => self.exec_FCVT_FMT_INT::<F>(inst)?,
+            FMV_X_FCLASS => self.exec_FMV_X_FCLASS::<F>(inst)?,
+            FMV_FMT_X => self.set_freg(rd(inst), F::box_bits(self.regs[rs1(inst)])),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
         }
         Ok(())
     }
 
-    fn execute_32(&mut self, inst: u32) -> Result<(), ProcessorError> {
+    fn exec_fp(&mut self, inst: u32) -> Result<(), Exception> {
+        match (op(inst), fmt(inst)) {
+            (OP_FP, FMT_S) if funct5(inst) == FCVT_FMT_FMT => match rs2(inst) as u64 {
+                FMT_D => self.exec_FCVT_FMT_FMT::<Single, Double>(inst),
+                _ => Err(Exception::IllegalInstruction(inst as u64)),
+            },
+            (OP_FP, FMT_D) if funct5(inst) == FCVT_FMT_FMT => match rs2(inst) as u64 {
+                FMT_S => self.exec_FCVT_FMT_FMT::<Double, Single>(inst),
+                _ => Err(Exception::IllegalInstruction(inst as u64)),
+            },
+            (OP_FP, FMT_S) => self.exec_op_fp::<Single>(inst),
+            (OP_FP, FMT_D) => self.exec_op_fp::<Double>(inst),
+            (_, FMT_S) => self.exec_fma::<Single>(inst),
+            (_, FMT_D) => self.exec_fma::<Double>(inst),
+            _ => Err(Exception::IllegalInstruction(inst as u64)),
+        }
+    }
+
+    fn hartid(&self) -> u64 {
+        self.csrs[MHARTID as usize]
+    }
+
+    fn exec_LR(&mut self, inst: u32, size: usize) -> Result<u64, Exception> {
+        let addr = self.regs[rs1(inst)];
+        let data = self.load(addr, size)?;
+        let paddr = self.translate(addr, AccessType::Load)?;
+        self.system_bus.reserve(self.hartid(), paddr);
+        Ok(data)
+    }
+
+    fn exec_SC(&mut self, inst: u32, size: usize) -> Result<u64, Exception> {
+        let addr = self.regs[rs1(inst)];
+        let hartid = self.hartid();
+        if addr & (size as u64 / 8 - 1) != 0 {
+            return Err(Exception::StoreAddressMisaligned(addr));
+        }
+        let paddr = self.translate(addr, AccessType::Store)?;
+        // SC always gives up the reservation, whether it succeeds or not
+        let success = self.system_bus.is_reserved(hartid, paddr);
+        self.system_bus.clear_reservation(hartid);
+        if !success {
+            return Ok(1);
+        }
+        self.store(self.regs[rs2(inst)], addr, size)?;
+        Ok(0)
+    }
+
+    fn exec_AMO_op(&mut self, inst: u32, size: usize) -> Result<u64, Exception> {
+        let addr = self.regs[rs1(inst)];
+        let src = self.regs[rs2(inst)];
+        let old = self.load(addr, size).map_err(Exception::as_store)?;
+        let (a, b) = if size == 32 {
+            (old as i32 as i64, src as i32 as i64)
+        } else {
+            (old as i64, src as i64)
+        };
+        let (ua, ub) = if size == 32 {
+            (old as u32 as u64, src as u32 as u64)
+        } else {
+            (old, src)
+        };
+        let new = match funct5(inst) {
+            AMOSWAP_W => src,
+            AMOADD_W  => old.wrapping_add(src),
+            AMOXOR_W  => old ^ src,
+            AMOAND_W  => old & src,
+            AMOOR_W   => old | src,
+            AMOMIN_W  => a.min(b) as u64,
+            AMOMAX_W  => a.max(b) as u64,
+            AMOMINU_W => ua.min(ub),
+            AMOMAXU_W => ua.max(ub),
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        };
+        self.store(new, addr, size)?;
+        Ok(old)
+    }
+
+    fn exec_amo(&mut self, inst: u32) -> Result<(), Exception> {
+        let size = match funct3(inst) {
+            AMO_WIDTH_W => 32,
+            AMO_WIDTH_D => 64,
+            _ => return Err(Exception::IllegalInstruction(inst as u64)),
+        };
+        let data = match funct5(inst) {
+            LR_W => self.exec_LR(inst, size)?,
+            SC_W => self.exec_SC(inst, size)?,
+            _ => self.exec_AMO_op(inst, size)?,
+        };
+        self.regs[rd(inst)] = if size == 32 { data as i32 as i64 as u64 } else { data };
+        Ok(())
+    }
+
+    fn execute_32(&mut self, inst: u32) -> Result<(), Exception> {
         let opcode = op(inst);
+        if matches!(opcode, LOAD_FP | STORE_FP | OP_FP | FMADD | FMSUB | FNMSUB | FNMADD) {
+            self.check_fs(inst as u64)?;
+        }
         match opcode {
-            JAL | JALR => {
+            SYSTEM if funct3(inst) == PRIV && csr(inst) == MRET => self.exec_MRET(inst),
+            SYSTEM if funct3(inst) == PRIV && csr(inst) == SRET => self.exec_SRET(inst),
+            JAL | JALR | B_TYPE => {
                 match opcode {
                     JAL    => self.exec_JAL(inst),
                     JALR   => self.exec_JALR(inst),
-                    _      => return Err(ProcessorError::NotYetImplemented),
+                    B_TYPE => self.exec_b_type(inst)?,
+                    _      => return Err(Exception::IllegalInstruction(inst as u64)),
                 }
                 Ok(())
             }
@@ -596,14 +1653,17 @@ impl Processor {
                     R_TYPE_64 => self.exec_r_type_64(inst)?,
                     I_TYPE => self.exec_i_type(inst)?,
                     I_TYPE_64 => self.exec_i_type_64(inst)?,
-                    B_TYPE => self.exec_b_type(inst)?,
                     LOAD   => self.exec_load(inst)?,
                     S_TYPE => self.exec_store(inst)?,
                     SYSTEM => self.exec_system(inst)?,
                     FENCE  => self.exec_fence(inst)?,
-                    _      => return Err(ProcessorError::NotYetImplemented),
+                    AMO_W  => self.exec_amo(inst)?,
+                    LOAD_FP  => self.exec_load_fp(inst)?,
+                    STORE_FP => self.exec_store_fp(inst)?,
+                    OP_FP | FMADD | FMSUB | FNMSUB | FNMADD => self.exec_fp(inst)?,
+                    _      => return Err(Exception::IllegalInstruction(inst as u64)),
                 }
-                self.pc = self.pc + 4;
+                self.pc = self.pc.wrapping_add(4);
                 Ok(())
             }
         }
@@ -611,13 +1671,13 @@ impl Processor {
 }
 
 impl Processor {
-    fn execute_48(&mut self, _: u64) -> Result<(), ProcessorError> {
-        todo!()
+    fn execute_48(&mut self, inst: u64) -> Result<(), Exception> {
+        Err(Exception::IllegalInstruction(inst))
     }
 }
 
 impl Processor {
-    fn execute_64(&mut self, inst: u64) -> Result<(), ProcessorError> {
+    fn execute_64(&mut self, inst: u64) -> Result<(), Exception> {
         let opcode = inst & 0x7f;
         let rd = (((inst) >> 7) & 0x1f) as usize;
         let rs1 = ((inst >> 15) & 0x1f) as usize;
@@ -633,14 +1693,14 @@ impl Processor {
                 // add
                 self.regs[rd] = self.regs[rs1].wrapping_add(self.regs[rs2]);
             }
-            _ => return Err(ProcessorError::NotYetImplemented),
+            _ => return Err(Exception::IllegalInstruction(inst)),
         }
         Ok(())
     }
 }
 
 impl Processor {
-    fn execute(&mut self, inst: Inst) -> Result<(), ProcessorError> {
+    fn execute(&mut self, inst: Inst) -> Result<(), Exception> {
         match inst {
             Inst::Inst16(inner) => self.execute_16(inner),
             Inst::Inst32(inner) => self.execute_32(inner),
@@ -671,10 +1731,178 @@ impl Processor {
         out
     }
 
+    /// Privilege level a trap is taken in: traps from S and U are handed
+    /// to S-mode when delegated through medeleg/mideleg.
+    fn trap_mode(&self, cause: u64, is_interrupt: bool) -> Mode {
+        let deleg = if is_interrupt { MIDELEG } else { MEDELEG };
+        if self.mode != Mode::Machine && (self.csrs[deleg as usize] >> cause) & 0x1 != 0 {
+            Mode::Supervisor
+        } else {
+            Mode::Machine
+        }
+    }
+
+    // Trap entry and return rewrite MPP without going through write_mstatus;
+    // with MPRV set that changes the mode loads and stores are checked in
+    fn set_trap_mstatus(&mut self, mstatus: u64) {
+        let old = self.csrs[MSTATUS as usize];
+        if (old | mstatus) & MSTATUS_MPRV != 0 && (old ^ mstatus) & (MSTATUS_MPRV | MSTATUS_MPP) != 0 {
+            self.tlb.flush();
+        }
+        self.csrs[MSTATUS as usize] = mstatus;
+    }
+
+    fn enter_trap(&mut self, cause: u64, tval: u64, is_interrupt: bool, target: Mode) {
+        let mstatus = self.csrs[MSTATUS as usize];
+        let mcause = if is_interrupt { cause | (1 << 63) } else { cause };
+        let prev = self.mode as u64;
+        if target == Mode::Supervisor {
+            self.csrs[SEPC as usize] = self.pc;
+            self.csrs[SCAUSE as usize] = mcause;
+            self.csrs[STVAL as usize] = tval;
+            // SPIE <- SIE, SIE <- 0, SPP <- previous mode
+            let sie = (mstatus & MSTATUS_SIE) << 4;
+            self.csrs[MSTATUS as usize] = (mstatus & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP))
+                | sie | ((prev & 0x1) << 8);
+            self.pc = trap_vector(self.csrs[STVEC as usize], cause, is_interrupt);
+        } else {
+            self.csrs[MEPC as usize] = self.pc;
+            self.csrs[MCAUSE as usize] = mcause;
+            self.csrs[MTVAL as usize] = tval;
+            // MPIE <- MIE, MIE <- 0, MPP <- previous mode
+            let mie = (mstatus & MSTATUS_MIE) << 4;
+            self.set_trap_mstatus((mstatus & !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP))
+                | mie | (prev << 11));
+            self.pc = trap_vector(self.csrs[MTVEC as usize], cause, is_interrupt);
+        }
+        self.set_mode(target);
+        self.system_bus.clear_reservation(self.hartid());
+    }
+
+    fn take_trap(&mut self, exception: Exception) -> Result<(), ProcessorError> {
+        if self.sbi.is_some() && exception == Exception::EnvironmentCallFromSMode {
+            return self.sbi_call();
+        }
+        let cause = exception.code();
+        let target = self.trap_mode(cause, false);
+        let tvec = if target == Mode::Supervisor { STVEC } else { MTVEC };
+        if is_double_fault(&exception, trap_vector(self.csrs[tvec as usize], cause, false)) {
+            return Err(ProcessorError::DoubleFault(exception));
+        }
+        self.enter_trap(cause, exception.tval(), false, target);
+        Ok(())
+    }
+
+    fn sbi_call(&mut self) -> Result<(), ProcessorError> {
+        let hartid = self.hartid();
+        let args: [u64; 8] = self.regs[10..18].try_into().unwrap();
+        let sbi = self.sbi.as_mut().unwrap();
+        let ret = sbi.call(hartid, &args, &mut self.system_bus, &self.csrs);
+        self.regs[10] = ret.error as u64;
+        if let Some(value) = ret.value {
+            self.regs[11] = value;
+        }
+        // Back to the instruction after the ECALL
+        self.pc = self.pc.wrapping_add(4);
+        match ret.effect {
+            Some(SbiEffect::RaiseSoftwareInterrupt) => self.csrs[MIP as usize] |= MIP_SSIP,
+            Some(SbiEffect::ClearSoftwareInterrupt) => self.csrs[MIP as usize] &= !MIP_SSIP,
+            Some(SbiEffect::SendIpiMask(addr)) => {
+                // A null mask means every hart
+                let mask = if addr == 0 { Ok(u64::MAX) } else { self.load(addr, 64) };
+                match mask {
+                    Ok(mask) if mask & (0x1 << (hartid & 0x3f)) != 0 => self.csrs[MIP as usize] |= MIP_SSIP,
+                    Ok(_) => {}
+                    Err(_) => self.regs[10] = SBI_ERR_INVALID_PARAM as u64,
+                }
+            }
+            Some(SbiEffect::FlushTlb) => self.tlb.flush(),
+            Some(SbiEffect::Suspend) => self.wfi = true,
+            Some(SbiEffect::Reset(code)) => return Err(ProcessorError::Exit(code)),
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn mip(&self) -> u64 {
+        self.csrs[MIP as usize] | if self.seip { MIP_SEIP } else { 0 }
+    }
+
+    // Mirror the CLINT and PLIC lines into mip
+    fn update_irqs(&mut self) {
+        let hartid = self.hartid();
+        let clint = &self.system_bus.clint;
+        let plic = &self.system_bus.plic;
+        let mut mip = self.csrs[MIP as usize] & !(MIP_MSIP | MIP_MTIP | MIP_MEIP);
+        if clint.msip(hartid) {
+            mip |= MIP_MSIP;
+        }
+        if clint.mtip(hartid) {
+            mip |= MIP_MTIP;
+        }
+        if plic.irq_pending(plic::context(hartid, false)) {
+            mip |= MIP_MEIP;
+        }
+        // The built-in SBI has no M-mode timer handler to forward the
+        // timer to S-mode, so the line drives STIP directly
+        if self.sbi.is_some() {
+            mip &= !MIP_STIP;
+            if clint.mtip(hartid) {
+                mip |= MIP_STIP;
+            }
+        }
+        self.csrs[MIP as usize] = mip;
+        self.seip = plic.irq_pending(plic::context(hartid, true));
+    }
+
+    /// Highest-priority interrupt that is pending, enabled and not masked
+    /// by the privilege level it would be taken in.
+    fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.mip() & self.csrs[MIE as usize];
+        if pending == 0 {
+            return None;
+        }
+        let mstatus = self.csrs[MSTATUS as usize];
+        let mideleg = self.csrs[MIDELEG as usize];
+        let m_enabled = self.mode < Mode::Machine || mstatus & MSTATUS_MIE != 0;
+        let s_enabled = self.mode < Mode::Supervisor
+            || (self.mode == Mode::Supervisor && mstatus & MSTATUS_SIE != 0);
+
+        // Interrupts for M-mode always come before those delegated to S-mode
+        let m_pending = if m_enabled { pending & !mideleg } else { 0 };
+        let s_pending = if s_enabled { pending & mideleg } else { 0 };
+        [m_pending, s_pending].into_iter().find_map(|set| {
+            INTERRUPT_PRIORITY.into_iter().find(|irq| set & irq.mask() != 0)
+        })
+    }
+
+    fn take_interrupt(&mut self, irq: Interrupt) {
+        let target = self.trap_mode(irq.code(), true);
+        self.enter_trap(irq.code(), 0, true, target);
+    }
+
     pub fn tick(&mut self) -> Result<(), ProcessorError> {
+        self.update_irqs();
+        // WFI resumes on any locally enabled interrupt, even a masked one
+        if self.wfi {
+            if self.mip() & self.csrs[MIE as usize] == 0 {
+                self.system_bus.tick();
+                return Ok(());
+            }
+            self.wfi = false;
+        }
+        if let Some(irq) = self.pending_interrupt() {
+            self.take_interrupt(irq);
+        }
+        let res = self.fetch().and_then(|inst| self.execute(inst));
         self.regs[0] = 0x00;
-        let inst = self.fetch()?;
-        self.execute(inst)?;
+        self.system_bus.tick();
+        if let Err(exception) = res {
+            self.take_trap(exception)?;
+        }
+        if let Some(code) = self.htif.as_mut().and_then(|htif| htif.poll(&mut self.system_bus)) {
+            return Err(ProcessorError::Exit(code));
+        }
         Ok(())
     }
 }
@@ -682,6 +1910,11 @@ impl Processor {
 #[cfg(test)]
 mod tests {
     use crate::Processor;
+    use crate::processor::{Inst, Mode};
+    use crate::errors::ProcessorError;
+    use crate::fpu::*;
+    use crate::opcodes::*;
+    use crate::trap::Exception;
 
     use super::SystemBus;
     use super::SystemBusMap;
@@ -700,7 +1933,7 @@ mod tests {
         let inst = 0x0020_d1b3;
         cpu.exec_SRL(inst);
 
-        assert_eq!(cpu.regs[3], 0xc000_0000_0000_0000);
+        assert_eq!(cpu.regs[3], 0x4000_0000_0000_0000);
     }
 
     #[test]
@@ -712,6 +1945,1011 @@ mod tests {
         let inst = 0x0020_d1b3;
         cpu.exec_SRA(inst);
 
+        assert_eq!(cpu.regs[3], 0xc000_0000_0000_0000);
+    }
+
+    #[test]
+    fn shift_amount_test() {
+        let mut cpu = make_dummy_processor();
+
+        // Only the low 6 bits of rs2 count: 65 shifts by 1
+        cpu.regs[1] = 0x8000_0000_0000_0000;
+        cpu.regs[2] = 65;
+        // sll x3, x1, x2
+        cpu.exec_r_type(0x0020_91b3).unwrap();
+        assert_eq!(cpu.regs[3], 0);
+        // srl x3, x1, x2
+        cpu.exec_r_type(0x0020_d1b3).unwrap();
         assert_eq!(cpu.regs[3], 0x4000_0000_0000_0000);
+        // sra x3, x1, x2
+        cpu.exec_r_type(0x4020_d1b3).unwrap();
+        assert_eq!(cpu.regs[3], 0xc000_0000_0000_0000);
+    }
+
+    #[test]
+    fn r_type_funct7_test() {
+        let mut cpu = make_dummy_processor();
+
+        // xor x3, x1, x2, then sll/slt/sltu/xor/or/and with the reserved funct7 = 0x20
+        cpu.exec_r_type(0x0020_c1b3).unwrap();
+        for inst in [0x4020_c1b3, 0x4020_91b3, 0x4020_a1b3, 0x4020_b1b3, 0x4020_e1b3, 0x4020_f1b3] {
+            assert_eq!(cpu.exec_r_type(inst), Err(Exception::IllegalInstruction(inst as u64)));
+        }
+    }
+
+    #[test]
+    fn mulh_test() {
+        let mut cpu = make_dummy_processor();
+
+        cpu.regs[1] = (-2i64) as u64;
+        cpu.regs[2] = 0x8000_0000_0000_0000;
+        // mulh x3, x1, x2
+        cpu.exec_r_type(0x0220_91b3).unwrap();
+        assert_eq!(cpu.regs[3], 0x1);
+        // mulhu x3, x1, x2
+        cpu.exec_r_type(0x0220_b1b3).unwrap();
+        assert_eq!(cpu.regs[3], 0x7fff_ffff_ffff_ffff);
+        // mulhsu x3, x1, x2
+        cpu.exec_r_type(0x0220_a1b3).unwrap();
+        assert_eq!(cpu.regs[3], 0xffff_ffff_ffff_ffff);
+    }
+
+    #[test]
+    fn div_by_zero_and_overflow_test() {
+        let mut cpu = make_dummy_processor();
+
+        cpu.regs[1] = 0x8000_0000_0000_0000;
+        cpu.regs[2] = 0x0;
+        // div x3, x1, x2
+        cpu.exec_r_type(0x0220_c1b3).unwrap();
+        assert_eq!(cpu.regs[3], u64::MAX);
+        // remu x3, x1, x2
+        cpu.exec_r_type(0x0220_f1b3).unwrap();
+        assert_eq!(cpu.regs[3], 0x8000_0000_0000_0000);
+
+        cpu.regs[2] = u64::MAX;
+        // div x3, x1, x2
+        cpu.exec_r_type(0x0220_c1b3).unwrap();
+        assert_eq!(cpu.regs[3], 0x8000_0000_0000_0000);
+        // rem x3, x1, x2
+        cpu.exec_r_type(0x0220_e1b3).unwrap();
+        assert_eq!(cpu.regs[3], 0x0);
+    }
+
+    #[test]
+    fn w_variants_sign_extend_test() {
+        let mut cpu = make_dummy_processor();
+
+        cpu.regs[1] = 0x0000_0000_8000_0000;
+        cpu.regs[2] = u64::MAX;
+        // divw x3, x1, x2
+        cpu.exec_r_type_64(0x0220_c1bb).unwrap();
+        assert_eq!(cpu.regs[3], 0xffff_ffff_8000_0000);
+        // divuw x3, x1, x2
+        cpu.exec_r_type_64(0x0220_d1bb).unwrap();
+        assert_eq!(cpu.regs[3], 0x0);
+        // mulw x3, x1, x1
+        cpu.exec_r_type_64(0x0210_81bb).unwrap();
+        assert_eq!(cpu.regs[3], 0x0);
+    }
+
+    #[test]
+    fn amo_test() {
+        let mut cpu = make_dummy_processor();
+
+        cpu.system_bus.store(0xffff_fffe, 0x8000_0100, 32).unwrap();
+        cpu.regs[1] = 0x8000_0100;
+        cpu.regs[2] = 0x5;
+        // amoadd.w x3, x2, (x1)
+        cpu.exec_amo(0x0020_a1af).unwrap();
+        assert_eq!(cpu.regs[3], 0xffff_ffff_ffff_fffe);
+        assert_eq!(cpu.system_bus.load(0x8000_0100, 32).unwrap(), 0x3);
+        // amomin.w x3, x2, (x1)
+        cpu.exec_amo(0x8020_a1af).unwrap();
+        assert_eq!(cpu.regs[3], 0x3);
+        assert_eq!(cpu.system_bus.load(0x8000_0100, 32).unwrap(), 0x3);
+        // amomaxu.d x3, x2, (x1)
+        cpu.exec_amo(0xe020_b1af).unwrap();
+        assert_eq!(cpu.regs[3], 0x3);
+        assert_eq!(cpu.system_bus.load(0x8000_0100, 64).unwrap(), 0x5);
+    }
+
+    #[test]
+    fn lr_sc_test() {
+        let mut cpu = make_dummy_processor();
+
+        cpu.regs[1] = 0x8000_0100;
+        cpu.regs[2] = 0x42;
+        // lr.d x3, (x1); sc.d x4, x2, (x1)
+        cpu.exec_amo(0x1000_b1af).unwrap();
+        cpu.exec_amo(0x1820_b22f).unwrap();
+        assert_eq!(cpu.regs[4], 0x0);
+        assert_eq!(cpu.system_bus.load(0x8000_0100, 64).unwrap(), 0x42);
+
+        // The reservation is consumed by the first SC
+        cpu.exec_amo(0x1820_b22f).unwrap();
+        assert_eq!(cpu.regs[4], 0x1);
+
+        // A store from another agent breaks the reservation
+        cpu.exec_amo(0x1000_b1af).unwrap();
+        cpu.system_bus.store(0x0, 0x8000_0104, 32).unwrap();
+        cpu.exec_amo(0x1820_b22f).unwrap();
+        assert_eq!(cpu.regs[4], 0x1);
+    }
+
+    #[test]
+    fn fp_nan_boxing_test() {
+        let mut cpu = make_dummy_processor();
+
+        // 1.0f without the upper ones reads as the canonical NaN
+        cpu.fregs[1] = 0x0000_0000_3f80_0000;
+        cpu.fregs[2] = 0xffff_ffff_3f80_0000;
+        // fadd.s f3, f1, f2
+        cpu.exec_fp(0x0020_81d3).unwrap();
+        assert_eq!(cpu.fregs[3], 0xffff_ffff_7fc0_0000);
+
+        cpu.fregs[1] = 0xffff_ffff_3f80_0000;
+        cpu.exec_fp(0x0020_81d3).unwrap();
+        assert_eq!(cpu.fregs[3], 0xffff_ffff_4000_0000);
+        assert_eq!(cpu.read_csr(FFLAGS), 0x0);
+    }
+
+    #[test]
+    fn fp_off_test() {
+        let mut cpu = make_dummy_processor();
+        cpu.regs[10] = 0x8000_0100;
+        cpu.regs[8] = 0x8000_0100;
+
+        // FS is Off out of reset: fadd.s, flw f1, 0(a0), c.fld and csrr a0, fflags all trap
+        assert_eq!(cpu.execute(Inst::Inst32(0x0020_81d3)), Err(Exception::IllegalInstruction(0x0020_81d3)));
+        assert_eq!(cpu.execute(Inst::Inst32(0x0005_2087)), Err(Exception::IllegalInstruction(0x0005_2087)));
+        assert_eq!(cpu.execute(Inst::Inst16(0x2000)), Err(Exception::IllegalInstruction(0x2000)));
+        assert_eq!(cpu.execute(Inst::Inst32(0x0010_2573)), Err(Exception::IllegalInstruction(0x0010_2573)));
+        assert_eq!(cpu.csrs[MSTATUS as usize] & MSTATUS_FS, 0);
+
+        // Initial, then csrr a1, fflags and the load work and make it Dirty
+        cpu.csrs[MSTATUS as usize] |= 0x1 << 13;
+        cpu.execute(Inst::Inst32(0x0010_25f3)).unwrap();
+        cpu.execute(Inst::Inst32(0x0005_2087)).unwrap();
+        assert_eq!(cpu.csrs[MSTATUS as usize] & MSTATUS_FS, MSTATUS_FS);
+    }
+
+    #[test]
+    fn fp_flags_and_rounding_test() {
+        let mut cpu = make_dummy_processor();
+
+        cpu.fregs[1] = 0xffff_ffff_3f80_0000;
+        cpu.fregs[2] = 0xffff_ffff_0000_0000;
+        // fdiv.s f3, f1, f2 (dynamic rounding)
+        cpu.exec_fp(0x1820_f1d3).unwrap();
+        assert_eq!(cpu.fregs[3], 0xffff_ffff_7f80_0000);
+        assert_eq!(cpu.read_csr(FFLAGS), FFLAGS_DZ);
+        assert_eq!(cpu.csrs[MSTATUS as usize] & MSTATUS_FS, MSTATUS_FS);
+
+        // 1.0 / 3.0, rounded up and down
+        cpu.write_csr(FCSR, 0x0);
+        cpu.fregs[2] = 0xffff_ffff_4040_0000;
+        cpu.write_csr(FRM, RM_RUP);
+        cpu.exec_fp(0x1820_f1d3).unwrap();
+        assert_eq!(cpu.fregs[3], 0xffff_ffff_3eaa_aaab);
+        cpu.write_csr(FRM, RM_RDN);
+        cpu.exec_fp(0x1820_f1d3).unwrap();
+        assert_eq!(cpu.fregs[3], 0xffff_ffff_3eaa_aaaa);
+        assert_eq!(cpu.read_csr(FCSR), (RM_RDN << 5) | FFLAGS_NX);
+    }
+
+    #[test]
+    fn fp_double_convert_test() {
+        let mut cpu = make_dummy_processor();
+
+        // 1/3 in double, narrowed to single under RNE
+        cpu.fregs[1] = 0x3fd5_5555_5555_5555;
+        // fcvt.s.d f3, f1
+        cpu.exec_fp(0x4010_81d3).unwrap();
+        assert_eq!(cpu.fregs[3], 0xffff_ffff_3eaa_aaab);
+        assert_eq!(cpu.read_csr(FFLAGS), FFLAGS_NX);
+
+        // fcvt.d.s f4, f3 widens exactly
+        cpu.exec_fp(0x4201_8253).unwrap();
+        assert_eq!(cpu.fregs[4], 0x3fd5_5555_6000_0000);
+
+        // A double bit pattern is not a boxed single
+        // fcvt.d.s f4, f1
+        cpu.exec_fp(0x4200_8253).unwrap();
+        assert_eq!(cpu.fregs[4], 0x7ff8_0000_0000_0000);
+
+        // fmv.x.d x5, f1
+        cpu.exec_fp(0xe200_82d3).unwrap();
+        assert_eq!(cpu.regs[5], 0x3fd5_5555_5555_5555);
+    }
+
+    #[test]
+    fn rvc_test() {
+        let mut cpu = make_dummy_processor();
+
+        let program: [u16; 8] = [
+            0x1101, // addi sp, sp, -32
+            0x57fd, // li a5, -1
+            0xf406, // sd ra, 40(sp)
+            0x4501, // li a0, 0
+            0xc119, // beqz a0, 6
+            0x0001, // nop
+            0x0001, // nop
+            0x8082, // ret
+        ];
+        for (i, inst) in program.iter().enumerate() {
+            cpu.system_bus.store(*inst as u64, 0x8000_0000 + 2 * i as u64, 16).unwrap();
+        }
+        cpu.set_pc(0x8000_0000);
+        cpu.regs[1] = 0x8000_0400;
+        cpu.regs[2] = 0x8000_1000;
+        cpu.regs[10] = 0x1;
+
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.pc, 0x8000_0008);
+        assert_eq!(cpu.regs[2], 0x8000_0fe0);
+        assert_eq!(cpu.regs[15], u64::MAX);
+        assert_eq!(cpu.regs[10], 0x0);
+        assert_eq!(cpu.system_bus.load(0x8000_1008, 64).unwrap(), 0x8000_0400);
+
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x8000_000e);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x8000_0400);
+    }
+
+    #[test]
+    fn ecall_mret_test() {
+        let mut cpu = make_dummy_processor();
+
+        // ecall; (handler) mret
+        cpu.system_bus.store(0x0000_0073, 0x8000_0000, 32).unwrap();
+        cpu.system_bus.store(0x3020_0073, 0x8000_0100, 32).unwrap();
+        cpu.write_csr(MTVEC, 0x8000_0100);
+        cpu.write_csr(MSTATUS, MSTATUS_MIE);
+        cpu.set_pc(0x8000_0000);
+
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x8000_0100);
+        assert_eq!(cpu.read_csr(MEPC), 0x8000_0000);
+        assert_eq!(cpu.read_csr(MCAUSE), 11);
+        assert_eq!(cpu.read_csr(MSTATUS) & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MPIE);
+        assert_eq!(cpu.read_csr(MSTATUS) & MSTATUS_MPP, MSTATUS_MPP);
+
+        // The handler skips the ecall before returning
+        cpu.write_csr(MEPC, cpu.read_csr(MEPC) + 4);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x8000_0004);
+        assert_eq!(cpu.read_csr(MSTATUS) & (MSTATUS_MIE | MSTATUS_MPIE), MSTATUS_MIE | MSTATUS_MPIE);
+        assert_eq!(cpu.read_csr(MSTATUS) & MSTATUS_MPP, 0x0);
+    }
+
+    #[test]
+    fn exception_tval_test() {
+        let mut cpu = make_dummy_processor();
+
+        // Vectored mode only affects interrupts
+        cpu.write_csr(MTVEC, 0x8000_0201);
+        cpu.set_pc(0x8000_0000);
+        // An all-zero parcel is an illegal instruction
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x8000_0200);
+        assert_eq!(cpu.read_csr(MCAUSE), 2);
+        assert_eq!(cpu.read_csr(MTVAL), 0);
+
+        // ld x3, 0(x1) from outside of DRAM
+        cpu.system_bus.store(0x0000_b183, 0x8000_0200, 32).unwrap();
+        cpu.regs[1] = 0x1000;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MEPC), 0x8000_0200);
+        assert_eq!(cpu.read_csr(MCAUSE), 5);
+        assert_eq!(cpu.read_csr(MTVAL), 0x1000);
+
+        // Misaligned ld
+        cpu.set_pc(0x8000_0200);
+        cpu.regs[1] = 0x8000_0004;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), 4);
+        assert_eq!(cpu.read_csr(MTVAL), 0x8000_0004);
+    }
+
+    #[test]
+    fn double_fault_test() {
+        let mut cpu = make_dummy_processor();
+
+        // mtvec points outside of memory: the handler fetch faults again
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x0);
+        assert!(matches!(cpu.tick(), Err(ProcessorError::DoubleFault(_))));
+    }
+
+    #[test]
+    fn delegation_test() {
+        let mut cpu = make_dummy_processor();
+        cpu.pmp_allow_all();
+
+        // mret into S-mode at 0x8000_0000, which runs an ecall
+        cpu.system_bus.store(0x0000_0073, 0x8000_0000, 32).unwrap();
+        cpu.system_bus.store(0x3020_0073, 0x8000_0100, 32).unwrap();
+        cpu.write_csr(MSTATUS, 0x1 << 11);
+        cpu.write_csr(MEPC, 0x8000_0000);
+        cpu.write_csr(STVEC, 0x8000_0200);
+        cpu.write_csr(MTVEC, 0x8000_0300);
+        cpu.set_pc(0x8000_0100);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.mode(), Mode::Supervisor);
+        assert_eq!(cpu.pc, 0x8000_0000);
+
+        // Not delegated: taken in M-mode
+        cpu.tick().unwrap();
+        assert_eq!(cpu.mode(), Mode::Machine);
+        assert_eq!(cpu.pc, 0x8000_0300);
+        assert_eq!(cpu.read_csr(MCAUSE), 9);
+        assert_eq!(cpu.read_csr(MSTATUS) & MSTATUS_MPP, 0x1 << 11);
+
+        // Delegated: taken in S-mode
+        cpu.write_csr(MEDELEG, 1 << 9);
+        cpu.set_pc(0x8000_0100);
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert_eq!(cpu.mode(), Mode::Supervisor);
+        assert_eq!(cpu.pc, 0x8000_0200);
+        assert_eq!(cpu.read_csr(SCAUSE), 9);
+        assert_eq!(cpu.read_csr(SEPC), 0x8000_0000);
+        assert_eq!(cpu.read_csr(SSTATUS) & MSTATUS_SPP, MSTATUS_SPP);
+    }
+
+    #[test]
+    fn csr_privilege_test() {
+        let mut cpu = make_dummy_processor();
+        cpu.pmp_allow_all();
+
+        // csrr a0, mstatus from S-mode
+        cpu.system_bus.store(0x3000_2573, 0x8000_0000, 32).unwrap();
+        // csrw mhartid, a0 from M-mode
+        cpu.system_bus.store(0xf141_1073, 0x8000_0100, 32).unwrap();
+        cpu.set_mode(Mode::Supervisor);
+        cpu.set_pc(0x8000_0000);
+        cpu.write_csr(MTVEC, 0x8000_0100);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), 2);
+        assert_eq!(cpu.read_csr(MTVAL), 0x3000_2573);
+
+        cpu.write_csr(MTVEC, 0x8000_0200);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), 2);
+        assert_eq!(cpu.read_csr(MEPC), 0x8000_0100);
+    }
+
+    #[test]
+    fn sv39_test() {
+        use crate::mmu::*;
+        let mut cpu = make_dummy_processor();
+        cpu.pmp_allow_all();
+        let pte = |pa: u64, flags: u64| ((pa >> 12) << 10) | flags;
+
+        // 0x1000 -> 0x8000_4000 through three levels, 0x8000_0000 as a gigapage
+        cpu.system_bus.store(pte(0x8000_2000, PTE_V), 0x8000_1000, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_0000, PTE_V | PTE_R | PTE_W | PTE_X), 0x8000_1010, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_1000, PTE_V | PTE_R), 0x8000_1018, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_3000, PTE_V), 0x8000_2000, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_4000, PTE_V | PTE_R | PTE_W | PTE_X), 0x8000_3008, 64).unwrap();
+        // sd a1, 8(a0)
+        cpu.system_bus.store(0x00b5_3423, 0x8000_4000, 32).unwrap();
+
+        cpu.write_csr(SATP, (SATP_MODE_SV39 << 60) | 0x80001);
+        cpu.write_csr(MTVEC, 0x8000_0100);
+        cpu.set_mode(Mode::Supervisor);
+        cpu.regs[10] = 0x1000;
+        cpu.regs[11] = 0xdead_beef;
+        cpu.set_pc(0x1000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x1004);
+        assert_eq!(cpu.system_bus.load(0x8000_4008, 64).unwrap(), 0xdead_beef);
+        let leaf = cpu.system_bus.load(0x8000_3008, 64).unwrap();
+        assert_eq!(leaf & (PTE_A | PTE_D), PTE_A | PTE_D);
+
+        assert_eq!(cpu.translate(0x8000_0010, AccessType::Load), Ok(0x8000_0010));
+        // Misaligned gigapage
+        assert_eq!(cpu.translate(0xc000_0000, AccessType::Load), Err(Exception::LoadPageFault(0xc000_0000)));
+        // Non-canonical address
+        assert_eq!(cpu.translate(0x40_0000_0000, AccessType::Load), Err(Exception::LoadPageFault(0x40_0000_0000)));
+
+        // Supervisor pages are not accessible from U-mode
+        cpu.set_mode(Mode::User);
+        cpu.set_pc(0x1000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), 12);
+        assert_eq!(cpu.read_csr(MTVAL), 0x1000);
+    }
+
+    #[test]
+    fn sum_mxr_test() {
+        use crate::mmu::*;
+        let mut cpu = make_dummy_processor();
+        cpu.pmp_allow_all();
+        let pte = |pa: u64, flags: u64| ((pa >> 12) << 10) | flags;
+
+        // 0x1000 is a user page, 0x2000 is execute-only
+        cpu.system_bus.store(pte(0x8000_2000, PTE_V), 0x8000_1000, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_3000, PTE_V), 0x8000_2000, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_4000, PTE_V | PTE_R | PTE_W | PTE_U), 0x8000_3008, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_5000, PTE_V | PTE_X), 0x8000_3010, 64).unwrap();
+        cpu.write_csr(SATP, (SATP_MODE_SV39 << 60) | 0x80001);
+        cpu.set_mode(Mode::Supervisor);
+
+        assert_eq!(cpu.translate(0x1000, AccessType::Load), Err(Exception::LoadPageFault(0x1000)));
+        assert_eq!(cpu.translate(0x2000, AccessType::Load), Err(Exception::LoadPageFault(0x2000)));
+        cpu.write_csr(MSTATUS, MSTATUS_SUM | MSTATUS_MXR);
+        assert_eq!(cpu.translate(0x1000, AccessType::Store), Ok(0x8000_4000));
+        assert_eq!(cpu.translate(0x2004, AccessType::Load), Ok(0x8000_5004));
+        assert_eq!(cpu.translate(0x1000, AccessType::Fetch), Err(Exception::InstructionPageFault(0x1000)));
+    }
+
+    #[test]
+    fn sv48_sv57_test() {
+        use crate::mmu::*;
+        let mut cpu = make_dummy_processor();
+        cpu.pmp_allow_all();
+        let pte = |pa: u64, flags: u64| ((pa >> 12) << 10) | flags;
+        let rwx = PTE_V | PTE_R | PTE_W | PTE_X;
+
+        // Sv48: 0x1000 -> 0x8000_5000 through four levels
+        cpu.system_bus.store(pte(0x8000_2000, PTE_V), 0x8000_1000, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_3000, PTE_V), 0x8000_2000, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_4000, PTE_V), 0x8000_3000, 64).unwrap();
+        cpu.system_bus.store(pte(0x8000_5000, rwx), 0x8000_4008, 64).unwrap();
+        // A 512 GiB page whose physical address is not aligned to 512 GiB
+        cpu.system_bus.store(pte(0x8000_0000, rwx), 0x8000_1008, 64).unwrap();
+        cpu.write_csr(SATP, (SATP_MODE_SV48 << 60) | 0x80001);
+        cpu.set_mode(Mode::Supervisor);
+
+        assert_eq!(cpu.translate(0x1010, AccessType::Load), Ok(0x8000_5010));
+        assert_eq!(cpu.translate(0x80_0000_0000, AccessType::Load),
+                   Err(Exception::LoadPageFault(0x80_0000_0000)));
+        // Canonical in Sv48 means bits 63:47 equal bit 47
+        assert_eq!(cpu.translate(0xffff_8000_0000_0000, AccessType::Store),
+                   Err(Exception::StorePageFault(0xffff_8000_0000_0000)));
+        assert_eq!(cpu.translate(0x8000_0000_0000, AccessType::Fetch),
+                   Err(Exception::InstructionPageFault(0x8000_0000_0000)));
+
+        // Sv57: one more level in front of the same tables
+        cpu.system_bus.store(pte(0x8000_1000, PTE_V), 0x8000_6000, 64).unwrap();
+        cpu.write_csr(SATP, (SATP_MODE_SV57 << 60) | 0x80006);
+        assert_eq!(cpu.translate(0x1010, AccessType::Load), Ok(0x8000_5010));
+        assert_eq!(cpu.translate(0x100_0000_0000_0000, AccessType::Load),
+                   Err(Exception::LoadPageFault(0x100_0000_0000_0000)));
+        assert_eq!(cpu.translate(0x2_0000_1000, AccessType::Load),
+                   Err(Exception::LoadPageFault(0x2_0000_1000)));
+
+        // Unsupported modes are ignored on write
+        cpu.write_csr(SATP, 0xb << 60);
+        assert_eq!(cpu.read_csr(SATP), (SATP_MODE_SV57 << 60) | 0x80006);
+    }
+
+    #[test]
+    fn tlb_test() {
+        let mut cpu = make_dummy_processor();
+
+        // ld a0, 0(a1) at 0x8000_0000
+        cpu.system_bus.store(0x0005_b503, 0x8000_0000, 32).unwrap();
+        cpu.system_bus.store(0x1234, 0x8000_0800, 64).unwrap();
+        cpu.regs[11] = 0x8000_0800;
+        for _ in 0..4 {
+            cpu.set_pc(0x8000_0000);
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.regs[10], 0x1234);
+        // One miss each for the fetch and the load, hits afterwards;
+        // a 32-bit instruction is fetched as two parcels
+        assert_eq!(cpu.tlb_stats().misses, 2);
+        assert_eq!(cpu.tlb_stats().hits, 10);
+
+        // Fast-path stores still break reservations
+        cpu.system_bus.reserve(0, 0x8000_0800);
+        cpu.store(0x1, 0x8000_0800, 8).unwrap();
+        cpu.store(0x5678, 0x8000_0800, 64).unwrap();
+        assert!(!cpu.system_bus.is_reserved(0, 0x8000_0800));
+        assert_eq!(cpu.load(0x8000_0800, 64), Ok(0x5678));
+
+        // Addresses off RAM are never cached
+        let misses = cpu.tlb_stats().misses;
+        assert!(cpu.load(0x1000, 64).is_err());
+        assert!(cpu.load(0x1000, 64).is_err());
+        assert_eq!(cpu.tlb_stats().misses, misses + 2);
+
+        // A new memory image invalidates everything
+        cpu.system_bus.bulk_store(vec![0; 0x1_0000]);
+        assert_eq!(cpu.load(0x8000_0800, 64), Ok(0));
+        assert_eq!(cpu.tlb_stats().misses, misses + 3);
+
+        // The fast path is little-endian at every width
+        cpu.store(0x1122_3344_5566_7788, 0x8000_0800, 64).unwrap();
+        cpu.store(0xaabb, 0x8000_0802, 16).unwrap();
+        assert_eq!(cpu.load(0x8000_0800, 32), Ok(0xaabb_7788));
+        assert_eq!(cpu.load(0x8000_0803, 8), Ok(0xaa));
+        assert_eq!(cpu.system_bus.load(0x8000_0800, 64).unwrap(), 0x1122_3344_aabb_7788);
+    }
+
+    #[test]
+    fn tlb_mprv_test() {
+        let mut cpu = make_dummy_processor();
+        cpu.csrs[MSTATUS as usize] |= MSTATUS_MPRV | MSTATUS_MPP;
+        assert!(cpu.load(0x8000_0800, 64).is_ok());
+
+        // mret back into M-mode leaves MPRV set with MPP = U, so loads are
+        // checked as U-mode ones and no PMP entry lets them through
+        cpu.system_bus.store(0x3020_0073, 0x8000_0000, 32).unwrap();
+        cpu.csrs[MEPC as usize] = 0x8000_0004;
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.mode(), Mode::Machine);
+        assert_eq!(cpu.load(0x8000_0800, 64), Err(Exception::LoadAccessFault(0x8000_0800)));
+
+        // A trap taken in M-mode moves MPP back to M
+        cpu.pmp_allow_all();
+        assert!(cpu.load(0x8000_0800, 64).is_ok());
+        let misses = cpu.tlb_stats().misses;
+        cpu.enter_trap(Exception::Breakpoint(0).code(), 0, false, Mode::Machine);
+        assert!(cpu.load(0x8000_0800, 64).is_ok());
+        assert_eq!(cpu.tlb_stats().misses, misses + 1);
+    }
+
+    // cargo test --release tlb_bench -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn tlb_bench() {
+        use crate::mmu::AccessType;
+        use crate::pmp;
+        use std::time::Instant;
+        let mut cpu = make_dummy_processor();
+        const N: u32 = 1_000_000;
+
+        // TLB hits read the DRAM slice directly
+        let start = Instant::now();
+        for i in 0..N {
+            cpu.load(0x8000_0000 + (i as u64 & 0xff8), 64).unwrap();
+        }
+        let hit = start.elapsed() / N;
+        let stats = cpu.tlb_stats();
+        println!("tlb: {} hits, {} misses, {:?} per load", stats.hits, stats.misses, hit);
+
+        // A miss translates, checks PMP and goes through the bus
+        let start = Instant::now();
+        for i in 0..N {
+            let paddr = cpu.translate(0x8000_0000 + (i as u64 & 0xff8), AccessType::Load).unwrap();
+            assert!(pmp::check(&cpu.csrs, paddr, 8, AccessType::Load, cpu.mode));
+            cpu.system_bus.load(paddr, 64).unwrap();
+        }
+        let miss = start.elapsed() / N;
+        println!("miss: {:?} per load", miss);
+        assert!(hit < miss);
+    }
+
+    #[test]
+    fn pmp_test() {
+        use crate::pmp::*;
+        let mut cpu = make_dummy_processor();
+
+        // 0: NAPOT 0x8000_0000..0x8000_1000 RX, 1: TOR up to 0x8000_8000 RW
+        cpu.write_csr(PMPADDR0, (0x8000_0000 >> 2) | 0x1ff);
+        cpu.write_csr(PMPADDR1, 0x8000_8000 >> 2);
+        cpu.write_csr(PMPCFG0, ((PMP_A_TOR | PMP_R | PMP_W) as u64) << 8
+            | (PMP_A_NAPOT | PMP_R | PMP_X) as u64);
+        cpu.set_mode(Mode::Supervisor);
+
+        assert!(cpu.load(0x8000_0ff8, 64).is_ok());
+        assert_eq!(cpu.store(0, 0x8000_0ff8, 64), Err(Exception::StoreAccessFault(0x8000_0ff8)));
+        assert_eq!(cpu.store(0, 0x8000_1000, 64), Ok(()));
+        // Outside every entry
+        assert_eq!(cpu.load(0x8000_8000, 64), Err(Exception::LoadAccessFault(0x8000_8000)));
+
+        // M-mode ignores unlocked entries, locked ones apply to it too
+        cpu.set_mode(Mode::Machine);
+        assert_eq!(cpu.store(0, 0x8000_0ff8, 64), Ok(()));
+        cpu.write_csr(PMPCFG0, cpu.read_csr(PMPCFG0) | PMP_L as u64);
+        assert_eq!(cpu.store(0, 0x8000_0ff8, 64), Err(Exception::StoreAccessFault(0x8000_0ff8)));
+        // Locked entries cannot be changed until reset
+        cpu.write_csr(PMPCFG0, 0);
+        cpu.write_csr(PMPADDR0, 0);
+        assert_eq!(cpu.read_csr(PMPCFG0) as u8, PMP_L | PMP_A_NAPOT | PMP_R | PMP_X);
+        assert_eq!(cpu.read_csr(PMPADDR0), (0x8000_0000 >> 2) | 0x1ff);
+        // Entry 1 was not locked
+        assert_eq!(cpu.read_csr(PMPCFG0) >> 8, 0);
+    }
+
+    #[test]
+    fn pmp_tor_lock_test() {
+        use crate::pmp::*;
+        let mut cpu = make_dummy_processor();
+
+        // A locked TOR entry also locks the pmpaddr below it
+        cpu.write_csr(PMPADDR0, 0x1000);
+        cpu.write_csr(PMPCFG0, ((PMP_L | PMP_A_TOR) as u64) << 8);
+        cpu.write_csr(PMPADDR0, 0x2000);
+        assert_eq!(cpu.read_csr(PMPADDR0), 0x1000);
+
+        // W without R is not a valid combination
+        cpu.write_csr(PMPCFG2, (PMP_A_NA4 | PMP_W) as u64);
+        assert_eq!(cpu.read_csr(PMPCFG2), PMP_A_NA4 as u64);
+    }
+
+    #[test]
+    fn pmp_unconfigured_test() {
+        let mut cpu = make_dummy_processor();
+
+        // With every pmpcfg zero only M-mode may touch memory
+        cpu.system_bus.store(0x0000_0013, 0x8000_0000, 32).unwrap();
+        assert!(cpu.load(0x8000_0000, 64).is_ok());
+        cpu.set_mode(Mode::Supervisor);
+        assert_eq!(cpu.load(0x8000_0000, 64), Err(Exception::LoadAccessFault(0x8000_0000)));
+        assert_eq!(cpu.store(0, 0x8000_0000, 64), Err(Exception::StoreAccessFault(0x8000_0000)));
+        cpu.set_mode(Mode::User);
+        assert_eq!(cpu.load(0x8000_0000, 32), Err(Exception::LoadAccessFault(0x8000_0000)));
+
+        // Fetches fault too, and the trap goes to M-mode
+        cpu.set_mode(Mode::Supervisor);
+        cpu.pc = 0x8000_0000;
+        cpu.tick().unwrap();
+        assert_eq!(cpu.mode(), Mode::Machine);
+        assert_eq!(cpu.read_csr(MCAUSE), Exception::InstructionAccessFault(0).code());
+
+        cpu.pmp_allow_all();
+        cpu.set_mode(Mode::Supervisor);
+        assert!(cpu.load(0x8000_0000, 64).is_ok());
+    }
+
+    #[test]
+    fn clint_test() {
+        let mut cpu = make_dummy_processor();
+
+        // nop loop
+        cpu.system_bus.store(0x0000_0013, 0x8000_0000, 32).unwrap();
+        cpu.store(5, 0x0200_4000, 64).unwrap();
+        for _ in 0..5 {
+            cpu.set_pc(0x8000_0000);
+            cpu.tick().unwrap();
+        }
+        assert_eq!(cpu.load(0x0200_bff8, 64), Ok(5));
+        assert_eq!(cpu.read_csr(MIP) & MIP_MTIP, 0);
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MIP) & MIP_MTIP, MIP_MTIP);
+        assert_eq!(cpu.read_csr(TIME), 6);
+
+        // Writing the upper half of mtimecmp clears the timer
+        cpu.store(0x1, 0x0200_4004, 32).unwrap();
+        cpu.store(0x1, 0x0200_0000, 32).unwrap();
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MIP) & (MIP_MTIP | MIP_MSIP), MIP_MSIP);
+        assert_eq!(cpu.load(0x0200_4000, 64), Ok(0x1_0000_0005));
+
+        // Software cannot set the machine-level bits itself
+        cpu.write_csr(MIP, MIP_MTIP | MIP_SSIP);
+        assert_eq!(cpu.read_csr(MIP), MIP_MSIP | MIP_SSIP);
+    }
+
+    #[test]
+    fn interrupt_priority_test() {
+        let mut cpu = make_dummy_processor();
+        cpu.pmp_allow_all();
+
+        cpu.system_bus.store(0x0000_0013, 0x8000_0000, 32).unwrap();
+        cpu.system_bus.store(0x0000_0013, 0x8000_0100, 32).unwrap();
+        cpu.system_bus.store(0x0000_0013, 0x8000_0200, 32).unwrap();
+        cpu.write_csr(MTVEC, 0x8000_0100);
+        cpu.write_csr(STVEC, 0x8000_0200);
+        cpu.write_csr(MIE, MIP_MSIP | MIP_MTIP | MIP_SSIP);
+        cpu.write_csr(MIDELEG, MIP_SSIP);
+        cpu.write_csr(MIP, MIP_SSIP);
+        cpu.system_bus.clint.set_msip(0, true);
+        cpu.system_bus.clint.set_mtimecmp(0, 0);
+
+        // Globally disabled in M-mode
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.pc, 0x8000_0004);
+
+        // MSI wins over MTI
+        cpu.write_csr(MSTATUS, MSTATUS_MIE);
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), (1 << 63) | 3);
+        assert_eq!(cpu.read_csr(MEPC), 0x8000_0000);
+        assert_eq!(cpu.pc, 0x8000_0104);
+
+        // M-level interrupts preempt S-mode regardless of SIE, and come
+        // before the delegated SSI
+        cpu.system_bus.clint.set_msip(0, false);
+        cpu.set_mode(Mode::Supervisor);
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), (1 << 63) | 7);
+        assert_eq!(cpu.mode(), Mode::Machine);
+
+        // With only SSI left it goes to S-mode
+        cpu.system_bus.clint.set_mtimecmp(0, u64::MAX);
+        cpu.set_mode(Mode::User);
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(SCAUSE), (1 << 63) | 1);
+        assert_eq!(cpu.mode(), Mode::Supervisor);
+        assert_eq!(cpu.pc, 0x8000_0204);
+    }
+
+    #[test]
+    fn wfi_test() {
+        let mut cpu = make_dummy_processor();
+        cpu.pmp_allow_all();
+
+        // wfi; nop
+        cpu.system_bus.store(0x1050_0073, 0x8000_0000, 32).unwrap();
+        cpu.system_bus.store(0x0000_0013, 0x8000_0004, 32).unwrap();
+        cpu.system_bus.store(0x0000_0013, 0x8000_0008, 32).unwrap();
+        cpu.write_csr(MIE, MIP_MTIP);
+        cpu.system_bus.clint.set_mtimecmp(0, 10);
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert!(cpu.is_waiting());
+        for _ in 0..8 {
+            cpu.tick().unwrap();
+        }
+        assert!(cpu.is_waiting());
+        assert_eq!(cpu.pc, 0x8000_0004);
+
+        // The timer wakes the hart, which resumes without trapping as MIE is clear
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert!(!cpu.is_waiting());
+        assert_eq!(cpu.pc, 0x8000_0008);
+
+        // Illegal from U-mode
+        cpu.system_bus.clint.set_mtimecmp(0, u64::MAX);
+        cpu.write_csr(MTVEC, 0x8000_0004);
+        cpu.set_mode(Mode::User);
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), 2);
+    }
+
+    #[test]
+    fn plic_test() {
+        let mut cpu = make_dummy_processor();
+
+        cpu.system_bus.store(0x0000_0013, 0x8000_0000, 32).unwrap();
+        cpu.system_bus.store(0x0000_0013, 0x8000_0100, 32).unwrap();
+        cpu.write_csr(MTVEC, 0x8000_0100);
+        cpu.write_csr(MIE, MIP_MEIP | MIP_SEIP);
+        cpu.write_csr(MSTATUS, MSTATUS_MIE);
+
+        // Sources 3 and 5 enabled for M-mode, 5 has the higher priority
+        cpu.store(1, 0x0c00_000c, 32).unwrap();
+        cpu.store(2, 0x0c00_0014, 32).unwrap();
+        cpu.store((1 << 3) | (1 << 5), 0x0c00_2000, 32).unwrap();
+        cpu.system_bus.plic.set_irq(3, true);
+        cpu.system_bus.plic.set_irq(5, true);
+        assert_eq!(cpu.load(0x0c00_1000, 32), Ok((1 << 3) | (1 << 5)));
+
+        cpu.set_pc(0x8000_0000);
+        cpu.tick().unwrap();
+        assert_eq!(cpu.read_csr(MCAUSE), (1 << 63) | 11);
+        assert_eq!(cpu.load(0x0c20_0004, 32), Ok(5));
+        assert_eq!(cpu.load(0x0c20_0004, 32), Ok(3));
+        assert_eq!(cpu.load(0x0c20_0004, 32), Ok(0));
+
+        // Claimed sources stay quiet until completed
+        cpu.update_irqs();
+        assert_eq!(cpu.read_csr(MIP) & MIP_MEIP, 0);
+        cpu.system_bus.plic.set_irq(3, false);
+        cpu.store(3, 0x0c20_0004, 32).unwrap();
+        cpu.store(5, 0x0c20_0004, 32).unwrap();
+        cpu.update_irqs();
+        assert_eq!(cpu.read_csr(MIP) & MIP_MEIP, MIP_MEIP);
+
+        // The threshold masks priorities at or below it
+        cpu.store(2, 0x0c20_0000, 32).unwrap();
+        cpu.update_irqs();
+        assert_eq!(cpu.read_csr(MIP) & MIP_MEIP, 0);
+
+        // S-mode context of hart 0
+        cpu.store(1 << 5, 0x0c00_2080, 32).unwrap();
+        cpu.update_irqs();
+        assert_eq!(cpu.read_csr(MIP) & MIP_SEIP, MIP_SEIP);
+        // Software's own SEIP bit is kept apart from the PLIC line
+        cpu.write_csr(MIP, 0);
+        assert_eq!(cpu.read_csr(MIP) & MIP_SEIP, MIP_SEIP);
+        assert_eq!(cpu.csrs[MIP as usize] & MIP_SEIP, 0);
+    }
+
+    #[test]
+    fn uart_test() {
+        use crate::test_util::Capture;
+        use crate::uart::*;
+        use std::sync::mpsc;
+
+        let mut cpu = make_dummy_processor();
+        let output = Capture::default();
+        let (tx, rx) = mpsc::channel();
+        let mut uart = Uart::new();
+        uart.set_output(Box::new(output.clone()));
+        uart.set_input(rx);
+        cpu.system_bus.register(UART_BASE, UART_SIZE, Box::new(uart), Some(UART_IRQ)).unwrap();
+
+        // li a0, 0x1000_0000; li a1, 'h'; sb a1, 0(a0); li a1, 'i'; sb a1, 0(a0)
+        let prog = [0x1000_0537, 0x0680_0593, 0x00b5_0023, 0x0690_0593, 0x00b5_0023];
+        for (i, inst) in prog.iter().enumerate() {
+            cpu.system_bus.store(*inst, 0x8000_0000 + 4 * i as u64, 32).unwrap();
+        }
+        cpu.set_pc(0x8000_0000);
+        for _ in 0..prog.len() {
+            cpu.tick().unwrap();
+        }
+        assert_eq!(output.contents(), b"hi");
+
+        // Received bytes raise the PLIC source once ERBFI is set
+        cpu.store(0x1, 0x1000_0002, 8).unwrap();
+        cpu.store(0x1, 0x1000_0001, 8).unwrap();
+        cpu.store(0x1, 0x0c00_0028, 32).unwrap();
+        cpu.store(1 << 10, 0x0c00_2000, 32).unwrap();
+        tx.send(b'x').unwrap();
+        cpu.system_bus.tick();
+        cpu.update_irqs();
+        assert_eq!(cpu.load(0x1000_0005, 8).unwrap() & 0x1, 0x1);
+        assert_eq!(cpu.load(0x1000_0002, 8), Ok(0xc4));
+        assert_eq!(cpu.read_csr(MIP) & MIP_MEIP, MIP_MEIP);
+        assert_eq!(cpu.load(0x1000_0000, 8), Ok(b'x' as u64));
+        assert_eq!(cpu.load(0x1000_0005, 8).unwrap() & 0x1, 0x0);
+        cpu.system_bus.tick();
+        assert_eq!(cpu.load(0x0c20_0004, 32), Ok(0));
+    }
+
+    #[test]
+    fn device_test() {
+        use crate::device::{Device, Dma};
+        use crate::errors::SystemBusError;
+
+        // Copies its register to RAM on every tick and raises its line when non-zero
+        struct Mailbox(u64);
+        impl Device for Mailbox {
+            fn load(&mut self, offset: u64, size: usize) -> Result<u64, SystemBusError> {
+                Ok(self.0 >> (8 * offset))
+            }
+            fn store(&mut self, data: u64, offset: u64, size: usize) -> Result<(), SystemBusError> {
+                self.0 = data;
+                Ok(())
+            }
+            fn tick(&mut self, dma: &mut Dma) {
+                dma.store(self.0, 0x8000_0800, 64).unwrap();
+            }
+            fn irq(&self) -> bool {
+                self.0 != 0
+            }
+        }
+
+        let mut cpu = make_dummy_processor();
+        cpu.system_bus.register(0x2000_0000, 0x10, Box::new(Mailbox(0)), Some(1)).unwrap();
+        // Overlaps with the mailbox, either end of DRAM, the CLINT and the PLIC
+        assert!(matches!(cpu.system_bus.register(0x2000_0008, 0x10, Box::new(Mailbox(0)), None),
+                         Err(SystemBusError::Overlap)));
+        assert!(matches!(cpu.system_bus.register(0x7fff_f000, 0x2000, Box::new(Mailbox(0)), None),
+                         Err(SystemBusError::Overlap)));
+        assert!(matches!(cpu.system_bus.register(0x8000_fff0, 0x20, Box::new(Mailbox(0)), None),
+                         Err(SystemBusError::Overlap)));
+        assert!(matches!(cpu.system_bus.register(0x0200_8000, 0x10, Box::new(Mailbox(0)), None),
+                         Err(SystemBusError::Overlap)));
+        assert!(matches!(cpu.system_bus.register(0x0c20_0000, 0x10, Box::new(Mailbox(0)), None),
+                         Err(SystemBusError::Overlap)));
+        cpu.system_bus.register(0x1fff_fff0, 0x10, Box::new(Mailbox(0x77)), None).unwrap();
+
+        cpu.store(0x1234, 0x2000_0000, 64).unwrap();
+        assert_eq!(cpu.load(0x2000_0001, 8), Ok(0x12));
+        assert_eq!(cpu.load(0x1fff_fff0, 64), Ok(0x77));
+        assert!(cpu.load(0x2000_0010, 64).is_err());
+
+        cpu.store(1, 0x0c00_0004, 32).unwrap();
+        cpu.store(1 << 1, 0x0c00_2000, 32).unwrap();
+        cpu.system_bus.tick();
+        cpu.update_irqs();
+        assert_eq!(cpu.system_bus.load(0x8000_0800, 64).unwrap(), 0x1234);
+        assert_eq!(cpu.read_csr(MIP) & MIP_MEIP, MIP_MEIP);
+    }
+
+    #[test]
+    fn load_dtb_test() {
+        let mut cpu = make_dummy_processor();
+        let addr = cpu.load_dtb(Some("console=hvc0")).unwrap();
+        assert_eq!(cpu.regs[10], 0);
+        assert_eq!(cpu.regs[11], addr);
+        assert_eq!(addr & 0x7, 0);
+        // Big-endian magic, and the blob fits below the end of RAM
+        assert_eq!(cpu.load(addr, 32), Ok(0xedfe_0dd0));
+        let size = (cpu.load(addr + 4, 32).unwrap() as u32).swap_bytes() as u64;
+        assert!(addr + size <= 0x8001_0000);
+    }
+
+    #[test]
+    fn htif_exit_test() {
+        let mut cpu = make_dummy_processor();
+        cpu.attach_htif(crate::htif::Htif::new(0x8000_1000, None));
+        cpu.store(0x13, 0x8000_0000, 32).unwrap();
+        cpu.store(0x13, 0x8000_0004, 32).unwrap();
+        cpu.set_pc(0x8000_0000);
+        assert!(cpu.tick().is_ok());
+        cpu.store((5 << 1) | 1, 0x8000_1000, 64).unwrap();
+        assert!(matches!(cpu.tick(), Err(ProcessorError::Exit(5))));
+    }
+
+    #[test]
+    fn sbi_test() {
+        use crate::sbi::*;
+        use crate::test_util::Capture;
+        use crate::uart::*;
+
+        let mut cpu = make_dummy_processor();
+        let output = Capture::default();
+        let mut uart = Uart::new();
+        uart.set_output(Box::new(output.clone()));
+        cpu.system_bus.register(UART_BASE, UART_SIZE, Box::new(uart), Some(UART_IRQ)).unwrap();
+
+        // A page of ecalls, entered in S-mode
+        for i in 0..16 {
+            cpu.system_bus.store(0x0000_0073, 0x8000_1000 + 4 * i, 32).unwrap();
+        }
+        cpu.boot_supervisor(0x8000_1000);
+        assert_eq!(cpu.mode(), Mode::Supervisor);
+        let call = |cpu: &mut Processor, ext: u64, fid: u64, a0: u64, a1: u64| {
+            cpu.regs[17] = ext;
+            cpu.regs[16] = fid;
+            cpu.regs[10] = a0;
+            cpu.regs[11] = a1;
+            let pc = cpu.pc;
+            let res = cpu.tick();
+            if res.is_ok() {
+                assert_eq!(cpu.pc, pc + 4);
+            }
+            res.map(|_| (cpu.regs[10] as i64, cpu.regs[11]))
+        };
+
+        assert_eq!(call(&mut cpu, EXT_BASE, 0, 0, 0).unwrap(), (0, 0x0200_0000));
+        assert_eq!(call(&mut cpu, EXT_BASE, 3, EXT_HSM, 0).unwrap(), (0, 1));
+        assert_eq!(call(&mut cpu, EXT_BASE, 3, 0x4442_434e, 0).unwrap(), (0, 0));
+        assert_eq!(call(&mut cpu, 0x1234, 0, 0, 0).unwrap().0, SBI_ERR_NOT_SUPPORTED);
+        assert_eq!(call(&mut cpu, EXT_HSM, 2, 0, 0).unwrap(), (0, 0));
+
+        // The timer raises STIP once mtime passes it, and a new deadline clears it
+        let deadline = cpu.system_bus.clint.mtime() + 2;
+        call(&mut cpu, EXT_TIME, 0, deadline, 0).unwrap();
+        assert_eq!(cpu.mip() & MIP_STIP, 0);
+        call(&mut cpu, EXT_BASE, 0, 0, 0).unwrap();
+        cpu.update_irqs();
+        assert_eq!(cpu.mip() & MIP_STIP, MIP_STIP);
+        call(&mut cpu, EXT_TIME, 0, u64::MAX, 0).unwrap();
+        cpu.update_irqs();
+        assert_eq!(cpu.mip() & MIP_STIP, 0);
+
+        // IPI to ourselves, a mask naming a hart that does not exist
+        assert_eq!(call(&mut cpu, EXT_IPI, 0, 0x1, 0).unwrap().0, 0);
+        assert_eq!(cpu.mip() & MIP_SSIP, MIP_SSIP);
+        assert_eq!(call(&mut cpu, EXT_IPI, 0, 0x2, 0).unwrap().0, SBI_ERR_INVALID_PARAM);
+        // The legacy call reads its mask from memory, which has to be readable
+        cpu.csrs[MIP as usize] &= !MIP_SSIP;
+        assert_eq!(call(&mut cpu, EXT_LEGACY_SEND_IPI, 0, 0x1000, 0).unwrap().0, SBI_ERR_INVALID_PARAM);
+        assert_eq!(cpu.mip() & MIP_SSIP, 0);
+        cpu.system_bus.store(0x1, 0x8000_0800, 64).unwrap();
+        assert_eq!(call(&mut cpu, EXT_LEGACY_SEND_IPI, 0, 0x8000_0800, 0).unwrap().0, SBI_SUCCESS);
+        assert_eq!(cpu.mip() & MIP_SSIP, MIP_SSIP);
+
+        // Legacy console goes out through the UART
+        call(&mut cpu, EXT_LEGACY_CONSOLE_PUTCHAR, 0, b'k' as u64, 0).unwrap();
+        assert_eq!(output.contents(), b"k");
+        assert_eq!(call(&mut cpu, EXT_LEGACY_CONSOLE_GETCHAR, 0, 0, 0).unwrap().0, -1);
+
+        // Reboots are refused, shutdown stops with the reason as exit code
+        assert_eq!(call(&mut cpu, EXT_SRST, 0, 1, 0).unwrap().0, SBI_ERR_NOT_SUPPORTED);
+        assert!(matches!(call(&mut cpu, EXT_SRST, 0, 0, 1), Err(ProcessorError::Exit(1))));
     }
 }
@@ -0,0 +1,231 @@
+use crate::opcodes::*;
+use crate::system_bus::SystemBus;
+use crate::uart::{LSR, LSR_DR, RBR_THR_DLL, UART_BASE};
+
+// Extension IDs, in a7
+pub const EXT_LEGACY_SET_TIMER: u64 = 0x00;
+pub const EXT_LEGACY_CONSOLE_PUTCHAR: u64 = 0x01;
+pub const EXT_LEGACY_CONSOLE_GETCHAR: u64 = 0x02;
+pub const EXT_LEGACY_CLEAR_IPI: u64 = 0x03;
+pub const EXT_LEGACY_SEND_IPI: u64 = 0x04;
+pub const EXT_LEGACY_REMOTE_FENCE_I: u64 = 0x05;
+pub const EXT_LEGACY_REMOTE_SFENCE_VMA: u64 = 0x06;
+pub const EXT_LEGACY_REMOTE_SFENCE_VMA_ASID: u64 = 0x07;
+pub const EXT_LEGACY_SHUTDOWN: u64 = 0x08;
+pub const EXT_BASE: u64 = 0x10;
+pub const EXT_TIME: u64 = 0x5449_4d45;
+pub const EXT_IPI: u64 = 0x0073_5049;
+pub const EXT_RFENCE: u64 = 0x5246_4e43;
+pub const EXT_HSM: u64 = 0x0048_534d;
+pub const EXT_SRST: u64 = 0x5352_5354;
+
+const EXTENSIONS: [u64; 15] = [
+    EXT_LEGACY_SET_TIMER, EXT_LEGACY_CONSOLE_PUTCHAR, EXT_LEGACY_CONSOLE_GETCHAR,
+    EXT_LEGACY_CLEAR_IPI, EXT_LEGACY_SEND_IPI, EXT_LEGACY_REMOTE_FENCE_I,
+    EXT_LEGACY_REMOTE_SFENCE_VMA, EXT_LEGACY_REMOTE_SFENCE_VMA_ASID, EXT_LEGACY_SHUTDOWN,
+    EXT_BASE, EXT_TIME, EXT_IPI, EXT_RFENCE, EXT_HSM, EXT_SRST,
+];
+
+pub const SBI_SUCCESS: i64 = 0;
+pub const SBI_ERR_FAILED: i64 = -1;
+pub const SBI_ERR_NOT_SUPPORTED: i64 = -2;
+pub const SBI_ERR_INVALID_PARAM: i64 = -3;
+pub const SBI_ERR_ALREADY_AVAILABLE: i64 = -6;
+
+// SBI specification 2.0
+const SPEC_VERSION: u64 = 0x2 << 24;
+// Not taken from the SBI implementation ID registry
+const IMPL_ID: u64 = 0x4a34;
+const IMPL_VERSION: u64 = 0x1;
+
+const HSM_STATE_STARTED: u64 = 0;
+const HSM_SUSPEND_RETENTIVE: u64 = 0x0;
+const HSM_SUSPEND_NON_RETENTIVE: u64 = 0x8000_0000;
+
+const SRST_SHUTDOWN: u64 = 0;
+const SRST_COLD_REBOOT: u64 = 1;
+const SRST_WARM_REBOOT: u64 = 2;
+const SRST_REASON_FAILURE: u64 = 1;
+
+/// What a call asks of the calling hart, beyond its return registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbiEffect {
+    RaiseSoftwareInterrupt,
+    ClearSoftwareInterrupt,
+    // Legacy send_ipi, with the S-mode virtual address of the hart mask
+    SendIpiMask(u64),
+    FlushTlb,
+    Suspend,
+    // Stop the machine with this exit code
+    Reset(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SbiReturn {
+    pub error: i64,
+    // None for legacy calls, which only return a0
+    pub value: Option<u64>,
+    pub effect: Option<SbiEffect>,
+}
+
+impl SbiReturn {
+    fn ok(value: u64) -> Self {
+        SbiReturn { error: SBI_SUCCESS, value: Some(value), effect: None }
+    }
+
+    fn err(error: i64) -> Self {
+        SbiReturn { error, value: Some(0), effect: None }
+    }
+
+    fn legacy(a0: i64) -> Self {
+        SbiReturn { error: a0, value: None, effect: None }
+    }
+
+    fn with(mut self, effect: SbiEffect) -> Self {
+        self.effect = Some(effect);
+        self
+    }
+}
+
+/// Supervisor Binary Interface served by the emulator itself in place of
+/// M-mode firmware, against the CLINT and UART of the bus.
+pub struct Sbi {}
+
+impl Sbi {
+    pub fn new() -> Self {
+        Sbi {}
+    }
+
+    /// Runs the call held in a0..a7 of hart `hartid`.
+    pub fn call(&mut self, hartid: u64, args: &[u64; 8], bus: &mut SystemBus, csrs: &[u64]) -> SbiReturn {
+        let (extension, function) = (args[7], args[6]);
+        match extension {
+            EXT_LEGACY_SET_TIMER => {
+                bus.clint.set_mtimecmp(hartid, args[0]);
+                SbiReturn::legacy(SBI_SUCCESS)
+            }
+            EXT_LEGACY_CONSOLE_PUTCHAR => {
+                let _ = bus.store(args[0] & 0xff, UART_BASE + RBR_THR_DLL, 8);
+                SbiReturn::legacy(SBI_SUCCESS)
+            }
+            EXT_LEGACY_CONSOLE_GETCHAR => {
+                let ready = bus.load(UART_BASE + LSR, 8).is_ok_and(|lsr| lsr as u8 & LSR_DR != 0);
+                match bus.load(UART_BASE + RBR_THR_DLL, 8) {
+                    Ok(byte) if ready => SbiReturn::legacy(byte as i64),
+                    _ => SbiReturn::legacy(-1),
+                }
+            }
+            EXT_LEGACY_CLEAR_IPI => SbiReturn::legacy(SBI_SUCCESS).with(SbiEffect::ClearSoftwareInterrupt),
+            EXT_LEGACY_SEND_IPI => SbiReturn::legacy(SBI_SUCCESS).with(SbiEffect::SendIpiMask(args[0])),
+            EXT_LEGACY_REMOTE_FENCE_I => SbiReturn::legacy(SBI_SUCCESS),
+            EXT_LEGACY_REMOTE_SFENCE_VMA | EXT_LEGACY_REMOTE_SFENCE_VMA_ASID => {
+                SbiReturn::legacy(SBI_SUCCESS).with(SbiEffect::FlushTlb)
+            }
+            EXT_LEGACY_SHUTDOWN => SbiReturn::legacy(SBI_SUCCESS).with(SbiEffect::Reset(0)),
+            EXT_BASE => self.base(function, args, csrs),
+            EXT_TIME if function == 0 => {
+                bus.clint.set_mtimecmp(hartid, args[0]);
+                SbiReturn::ok(0)
+            }
+            EXT_IPI if function == 0 => match targets(hartid, args[0], args[1]) {
+                Ok(true) => SbiReturn::ok(0).with(SbiEffect::RaiseSoftwareInterrupt),
+                Ok(false) => SbiReturn::ok(0),
+                Err(error) => SbiReturn::err(error),
+            },
+            EXT_RFENCE => self.rfence(hartid, function, args),
+            EXT_HSM => self.hsm(hartid, function, args),
+            EXT_SRST if function == 0 => match (args[0], args[1]) {
+                (SRST_SHUTDOWN, reason) => {
+                    let code = if reason == SRST_REASON_FAILURE { 1 } else { 0 };
+                    SbiReturn::ok(0).with(SbiEffect::Reset(code))
+                }
+                // There is no way to restart the guest, so reboots are refused
+                (SRST_COLD_REBOOT | SRST_WARM_REBOOT, _) => SbiReturn::err(SBI_ERR_NOT_SUPPORTED),
+                (kind, _) if kind < 0xf000_0000 => SbiReturn::err(SBI_ERR_INVALID_PARAM),
+                _ => SbiReturn::err(SBI_ERR_NOT_SUPPORTED),
+            },
+            _ => SbiReturn::err(SBI_ERR_NOT_SUPPORTED),
+        }
+    }
+
+    fn base(&self, function: u64, args: &[u64; 8], csrs: &[u64]) -> SbiReturn {
+        match function {
+            0 => SbiReturn::ok(SPEC_VERSION),
+            1 => SbiReturn::ok(IMPL_ID),
+            2 => SbiReturn::ok(IMPL_VERSION),
+            3 => SbiReturn::ok(EXTENSIONS.contains(&args[0]) as u64),
+            4 => SbiReturn::ok(csrs[MVENDORID as usize]),
+            5 => SbiReturn::ok(csrs[MARCHID as usize]),
+            6 => SbiReturn::ok(csrs[MIMPID as usize]),
+            _ => SbiReturn::err(SBI_ERR_NOT_SUPPORTED),
+        }
+    }
+
+    fn rfence(&self, hartid: u64, function: u64, args: &[u64; 8]) -> SbiReturn {
+        let local = match targets(hartid, args[0], args[1]) {
+            Ok(local) => local,
+            Err(error) => return SbiReturn::err(error),
+        };
+        match function {
+            // Instruction fetches are never cached
+            0 => SbiReturn::ok(0),
+            1 | 2 if local => SbiReturn::ok(0).with(SbiEffect::FlushTlb),
+            1 | 2 => SbiReturn::ok(0),
+            // No hypervisor extension
+            _ => SbiReturn::err(SBI_ERR_NOT_SUPPORTED),
+        }
+    }
+
+    // A single hart, always running
+    fn hsm(&self, hartid: u64, function: u64, args: &[u64; 8]) -> SbiReturn {
+        match function {
+            0 if args[0] == hartid => SbiReturn::err(SBI_ERR_ALREADY_AVAILABLE),
+            0 => SbiReturn::err(SBI_ERR_INVALID_PARAM),
+            // Stopping the only hart would leave nothing to run
+            1 => SbiReturn::err(SBI_ERR_FAILED),
+            2 if args[0] == hartid => SbiReturn::ok(HSM_STATE_STARTED),
+            2 => SbiReturn::err(SBI_ERR_INVALID_PARAM),
+            3 => match args[0] {
+                HSM_SUSPEND_RETENTIVE => SbiReturn::ok(0).with(SbiEffect::Suspend),
+                HSM_SUSPEND_NON_RETENTIVE => SbiReturn::err(SBI_ERR_NOT_SUPPORTED),
+                _ => SbiReturn::err(SBI_ERR_INVALID_PARAM),
+            },
+            _ => SbiReturn::err(SBI_ERR_NOT_SUPPORTED),
+        }
+    }
+}
+
+impl Default for Sbi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a (hart_mask, hart_mask_base) pair selects `hartid`, the only
+/// hart there is. Naming any other hart is an error.
+fn targets(hartid: u64, mask: u64, base: u64) -> Result<bool, i64> {
+    // A base of -1 selects every hart
+    if base == u64::MAX {
+        return Ok(true);
+    }
+    let bit = hartid.checked_sub(base).filter(|&bit| bit < 64);
+    let own = bit.map_or(0, |bit| 0x1 << bit);
+    if mask & !own != 0 {
+        return Err(SBI_ERR_INVALID_PARAM);
+    }
+    Ok(mask & own != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sbi_hart_mask_test() {
+        assert_eq!(targets(0, 0x1, 0), Ok(true));
+        assert_eq!(targets(0, 0, 0), Ok(false));
+        assert_eq!(targets(0, 0, u64::MAX), Ok(true));
+        assert_eq!(targets(0, 0x2, 0), Err(SBI_ERR_INVALID_PARAM));
+        assert_eq!(targets(0, 0x1, 1), Err(SBI_ERR_INVALID_PARAM));
+    }
+}
@@ -0,0 +1,53 @@
+#![allow(dead_code)]
+
+use std::io::{self, Write};
+
+use crate::loader::ElfImage;
+use crate::system_bus::SystemBus;
+
+// Bytes per line of the dump, RISCOF's default signature granularity
+const SIGNATURE_GRANULE: u64 = 4;
+
+/// Writes the riscv-arch-test signature, the memory between the
+/// `begin_signature` and `end_signature` symbols, one word per line as
+/// lowercase hex, as RISCOF expects from a DUT.
+pub fn write_signature(bus: &mut SystemBus, image: &ElfImage, out: &mut dyn Write) -> io::Result<()> {
+    let symbol = |name: &str| image.symbol(name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no {} symbol", name)));
+    let begin = symbol("begin_signature")?;
+    let end = symbol("end_signature")?;
+    let mut addr = begin;
+    while addr < end {
+        let word = bus.load(addr, 32)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("signature address {:#x} is not RAM", addr)))?;
+        writeln!(out, "{:08x}", word)?;
+        addr += SIGNATURE_GRANULE;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::system_bus::SystemBusMap;
+
+    use super::*;
+
+    #[test]
+    fn signature_test() {
+        let mut bus = SystemBus::new(SystemBusMap { dram_base_addr: 0x8000_0000, dram_size: 0x1_0000 });
+        bus.store(0x0123_4567_89ab_cdef, 0x8000_2000, 64).unwrap();
+        bus.store(0xdead_beef, 0x8000_2008, 32).unwrap();
+        let symbols = HashMap::from([
+            (String::from("begin_signature"), 0x8000_2000),
+            (String::from("end_signature"), 0x8000_200c),
+        ]);
+        let mut out = Vec::new();
+        write_signature(&mut bus, &ElfImage::new(0x8000_0000, symbols), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "89abcdef\n01234567\ndeadbeef\n");
+
+        let err = write_signature(&mut bus, &ElfImage::new(0x8000_0000, HashMap::new()), &mut Vec::new());
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,23 +1,54 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::clint::{Clint, CLINT_BASE, CLINT_SIZE};
+use crate::device::{Device, Dma};
 use crate::dram::Dram;
+use crate::plic::{Plic, PLIC_BASE, PLIC_SIZE};
 use crate::errors::SystemBusError;
+use crate::mmu::PAGE_SIZE;
 
 pub struct SystemBusMap {
     pub dram_base_addr: u64,
     pub dram_size: usize,
 }
 
+struct MappedDevice {
+    size: u64,
+    irq: Option<usize>,
+    device: Box<dyn Device>,
+}
+
 pub struct SystemBus {
     dram_base_addr: u64,
     dram_size: usize,
     dram: Dram,
+    pub clint: Clint,
+    pub plic: Plic,
+    // Peripherals by base address
+    devices: BTreeMap<u64, MappedDevice>,
+
+    // LR/SC reservation sets: hart id -> reserved granule address
+    reservations: HashMap<u64, u64>,
+
+    // Bumped whenever the memory map changes, so cached DRAM offsets
+    // held by the harts' TLBs go stale
+    generation: u64,
 }
 
+// Reservation granule size in bytes
+pub const RESERVATION_GRANULE: u64 = 8;
+
 impl SystemBus {
     pub fn new(map: SystemBusMap) -> Self {
         SystemBus {
             dram_base_addr: map.dram_base_addr,
             dram_size: map.dram_size,
             dram: Dram::new(map.dram_size),
+            clint: Clint::new(),
+            plic: Plic::new(),
+            devices: BTreeMap::new(),
+            reservations: HashMap::new(),
+            generation: 0,
         }
     }
 }
@@ -25,15 +56,125 @@ impl SystemBus {
 impl SystemBus {
     pub fn bulk_store(&mut self, data: Vec<u8>) {
         self.dram.bulk_store(data);
+        self.dram_size = self.dram.size();
+        self.generation += 1;
     }
 
     pub fn bulk_store_segment(&mut self, data: Vec<u8>, addr: u64) {
         self.dram.bulk_store_segment(data, addr);
+        self.generation += 1;
+    }
+
+    /// Maps `device` at [base, base + size), optionally wiring its
+    /// interrupt line to a PLIC source. Overlapping ranges are refused.
+    pub fn register(&mut self, base: u64, size: u64, device: Box<dyn Device>,
+                    irq: Option<usize>) -> Result<(), SystemBusError> {
+        let end = base.checked_add(size).ok_or(SystemBusError::InvalidAddress)?;
+        if size == 0 {
+            return Err(SystemBusError::InvalidAddress);
+        }
+        let overlaps = |start: u64, len: u64| base < start + len && start < end;
+        let fixed = [
+            (self.dram_base_addr, self.dram_size as u64),
+            (CLINT_BASE, CLINT_SIZE),
+            (PLIC_BASE, PLIC_SIZE),
+        ];
+        if fixed.iter().any(|&(start, len)| overlaps(start, len))
+            || self.devices.iter().any(|(&start, mapped)| overlaps(start, mapped.size)) {
+            return Err(SystemBusError::Overlap);
+        }
+        self.devices.insert(base, MappedDevice { size, irq, device });
+        self.generation += 1;
+        Ok(())
+    }
+
+    // Device mapped at `addr` and the offset into it
+    fn device(&mut self, addr: u64) -> Option<(&mut MappedDevice, u64)> {
+        let (&base, mapped) = self.devices.range_mut(..=addr).next_back()?;
+        if addr - base < mapped.size {
+            Some((mapped, addr - base))
+        } else {
+            None
+        }
+    }
+
+    /// Advances the devices by one processor cycle.
+    pub fn tick(&mut self) {
+        self.clint.tick();
+        let mut dma = Dma::new(self.dram_base_addr, &mut self.dram);
+        for mapped in self.devices.values_mut() {
+            mapped.device.tick(&mut dma);
+            if let Some(irq) = mapped.irq {
+                self.plic.set_irq(irq, mapped.device.irq());
+            }
+        }
+    }
+
+    pub fn dram_base(&self) -> u64 {
+        self.dram_base_addr
+    }
+
+    pub fn dram_size(&self) -> usize {
+        self.dram_size
     }
 
-    pub fn load(&self, addr: u64, size: usize) -> Result<u64, SystemBusError> {
+    /// Registered peripherals as (base, size, PLIC source, device), by address.
+    pub fn devices(&self) -> impl Iterator<Item = (u64, u64, Option<usize>, &dyn Device)> {
+        self.devices.iter().map(|(&base, mapped)| (base, mapped.size, mapped.irq, mapped.device.as_ref()))
+    }
+
+    /// Copies `data` into RAM at physical address `addr`.
+    pub fn write_bytes(&mut self, addr: u64, data: &[u8]) -> Result<(), SystemBusError> {
+        Dma::new(self.dram_base_addr, &mut self.dram).write(addr, data)?;
+        let end = addr + data.len() as u64;
+        self.reservations.retain(|_, granule| *granule + RESERVATION_GRANULE <= addr || *granule >= end);
+        Ok(())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// DRAM offset of the page holding `addr`, if the whole page is RAM.
+    pub fn dram_page_offset(&self, addr: u64) -> Option<usize> {
+        let page = addr & !(PAGE_SIZE - 1);
+        if self.dram_base_addr & (PAGE_SIZE - 1) != 0 || page < self.dram_base_addr {
+            return None;
+        }
+        let offset = page - self.dram_base_addr;
+        if offset + PAGE_SIZE > self.dram_size as u64 {
+            return None;
+        }
+        Some(offset as usize)
+    }
+
+    // TLB hits go straight to the backing slice instead of the byte-wise Dram accessors
+    pub fn load_dram(&self, offset: usize, size: usize) -> u64 {
+        let mem = &self.dram.as_slice()[offset..offset + size / 8];
+        match size {
+            8  => mem[0] as u64,
+            16 => u16::from_le_bytes(mem.try_into().unwrap()) as u64,
+            32 => u32::from_le_bytes(mem.try_into().unwrap()) as u64,
+            64 => u64::from_le_bytes(mem.try_into().unwrap()),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn store_dram(&mut self, data: u64, offset: usize, size: usize) {
+        self.invalidate_reservations(self.dram_base_addr + offset as u64, size);
+        let len = size / 8;
+        self.dram.as_mut_slice()[offset..offset + len].copy_from_slice(&data.to_le_bytes()[..len]);
+    }
+
+    pub fn load(&mut self, addr: u64, size: usize) -> Result<u64, SystemBusError> {
         if addr >= self.dram_base_addr && addr < self.dram_base_addr + self.dram_size as u64 {
             Ok(self.dram.load(addr - self.dram_base_addr, size))
+        } else if (CLINT_BASE..CLINT_BASE + CLINT_SIZE).contains(&addr) {
+            self.clint.load(addr - CLINT_BASE, size)
+        } else if (PLIC_BASE..PLIC_BASE + PLIC_SIZE).contains(&addr) {
+            self.plic.load(addr - PLIC_BASE, size)
+        } else if let Some((mapped, offset)) = self.device(addr) {
+            mapped.device.load(offset, size)
         } else {
             Err(SystemBusError::InvalidAddress)
         }
@@ -41,10 +182,42 @@ impl SystemBus {
 
     pub fn store(&mut self, data: u64, addr: u64, size: usize) -> Result<(), SystemBusError> {
         if addr >= self.dram_base_addr && addr < self.dram_base_addr + self.dram_size as u64 {
+            self.invalidate_reservations(addr, size);
             self.dram.store(data, addr - self.dram_base_addr, size);
             Ok(())
+        } else if (CLINT_BASE..CLINT_BASE + CLINT_SIZE).contains(&addr) {
+            self.clint.store(data, addr - CLINT_BASE, size)
+        } else if (PLIC_BASE..PLIC_BASE + PLIC_SIZE).contains(&addr) {
+            self.plic.store(data, addr - PLIC_BASE, size)
+        } else if let Some((mapped, offset)) = self.device(addr) {
+            mapped.device.store(data, offset, size)
         } else {
             Err(SystemBusError::InvalidAddress)
         }
     }
 }
+
+impl SystemBus {
+    pub fn reserve(&mut self, hartid: u64, addr: u64) {
+        self.reservations.insert(hartid, addr & !(RESERVATION_GRANULE - 1));
+    }
+
+    pub fn is_reserved(&self, hartid: u64, addr: u64) -> bool {
+        self.reservations.get(&hartid) == Some(&(addr & !(RESERVATION_GRANULE - 1)))
+    }
+
+    pub fn clear_reservation(&mut self, hartid: u64) {
+        self.reservations.remove(&hartid);
+    }
+
+    // Any store overlapping a reserved granule breaks the reservation,
+    // no matter which hart holds it
+    fn invalidate_reservations(&mut self, addr: u64, size: usize) {
+        if self.reservations.is_empty() {
+            return;
+        }
+        let first = addr & !(RESERVATION_GRANULE - 1);
+        let last = (addr + (size as u64 / 8).max(1) - 1) & !(RESERVATION_GRANULE - 1);
+        self.reservations.retain(|_, granule| *granule != first && *granule != last);
+    }
+}
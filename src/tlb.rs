@@ -0,0 +1,93 @@
+#![allow(dead_code)]
+
+use crate::mmu::{AccessType, PAGE_SIZE};
+
+const TLB_ENTRIES: usize = 256;
+
+// Guest virtual page -> offset of the backing page inside DRAM
+#[derive(Clone, Copy)]
+struct TlbEntry {
+    vpn: u64,
+    offset: usize,
+}
+
+// No virtual page number reaches u64::MAX, so this never matches
+const INVALID: TlbEntry = TlbEntry { vpn: u64::MAX, offset: 0 };
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TlbStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Direct-mapped software TLB caching RAM pages only, with separate
+/// entries for fetches, loads and stores so each one holds a page only
+/// after a walk has granted that kind of access.
+pub struct Tlb {
+    fetch: [TlbEntry; TLB_ENTRIES],
+    load: [TlbEntry; TLB_ENTRIES],
+    store: [TlbEntry; TLB_ENTRIES],
+
+    // Bus map generation the entries were filled under
+    generation: u64,
+    stats: TlbStats,
+}
+
+impl Tlb {
+    pub fn new() -> Self {
+        Tlb {
+            fetch: [INVALID; TLB_ENTRIES],
+            load: [INVALID; TLB_ENTRIES],
+            store: [INVALID; TLB_ENTRIES],
+            generation: 0,
+            stats: TlbStats::default(),
+        }
+    }
+
+    fn entries(&mut self, access: AccessType) -> &mut [TlbEntry; TLB_ENTRIES] {
+        match access {
+            AccessType::Fetch => &mut self.fetch,
+            AccessType::Load => &mut self.load,
+            AccessType::Store => &mut self.store,
+        }
+    }
+
+    /// DRAM offset of `vaddr` if its page is cached for `access`.
+    pub fn lookup(&mut self, vaddr: u64, access: AccessType, generation: u64) -> Option<usize> {
+        if generation != self.generation {
+            self.flush();
+            self.generation = generation;
+        }
+        let vpn = vaddr / PAGE_SIZE;
+        let entry = self.entries(access)[vpn as usize & (TLB_ENTRIES - 1)];
+        if entry.vpn == vpn {
+            self.stats.hits += 1;
+            Some(entry.offset + (vaddr & (PAGE_SIZE - 1)) as usize)
+        } else {
+            self.stats.misses += 1;
+            None
+        }
+    }
+
+    /// Caches the page of `vaddr`, backed by the DRAM page at `offset`.
+    pub fn insert(&mut self, vaddr: u64, offset: usize, access: AccessType) {
+        let vpn = vaddr / PAGE_SIZE;
+        self.entries(access)[vpn as usize & (TLB_ENTRIES - 1)] = TlbEntry { vpn, offset };
+    }
+
+    pub fn flush(&mut self) {
+        self.fetch = [INVALID; TLB_ENTRIES];
+        self.load = [INVALID; TLB_ENTRIES];
+        self.store = [INVALID; TLB_ENTRIES];
+    }
+
+    pub fn stats(&self) -> TlbStats {
+        self.stats
+    }
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
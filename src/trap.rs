@@ -0,0 +1,125 @@
+#![allow(dead_code)]
+
+/// Synchronous exceptions, carrying the value reported in `xtval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned(u64),
+    InstructionAccessFault(u64),
+    IllegalInstruction(u64),
+    Breakpoint(u64),
+    LoadAddressMisaligned(u64),
+    LoadAccessFault(u64),
+    StoreAddressMisaligned(u64),
+    StoreAccessFault(u64),
+    EnvironmentCallFromUMode,
+    EnvironmentCallFromSMode,
+    EnvironmentCallFromMMode,
+    InstructionPageFault(u64),
+    LoadPageFault(u64),
+    StorePageFault(u64),
+}
+
+impl Exception {
+    pub fn code(&self) -> u64 {
+        match self {
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAddressMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::EnvironmentCallFromUMode => 8,
+            Exception::EnvironmentCallFromSMode => 9,
+            Exception::EnvironmentCallFromMMode => 11,
+            Exception::InstructionPageFault(_) => 12,
+            Exception::LoadPageFault(_) => 13,
+            Exception::StorePageFault(_) => 15,
+        }
+    }
+
+    pub fn tval(&self) -> u64 {
+        match *self {
+            Exception::InstructionAddressMisaligned(tval)
+            | Exception::InstructionAccessFault(tval)
+            | Exception::IllegalInstruction(tval)
+            | Exception::Breakpoint(tval)
+            | Exception::LoadAddressMisaligned(tval)
+            | Exception::LoadAccessFault(tval)
+            | Exception::StoreAddressMisaligned(tval)
+            | Exception::StoreAccessFault(tval)
+            | Exception::InstructionPageFault(tval)
+            | Exception::LoadPageFault(tval)
+            | Exception::StorePageFault(tval) => tval,
+            Exception::EnvironmentCallFromUMode
+            | Exception::EnvironmentCallFromSMode
+            | Exception::EnvironmentCallFromMMode => 0,
+        }
+    }
+
+    /// AMOs report load-side faults as store/AMO faults.
+    pub fn as_store(self) -> Exception {
+        match self {
+            Exception::LoadAddressMisaligned(tval) => Exception::StoreAddressMisaligned(tval),
+            Exception::LoadAccessFault(tval) => Exception::StoreAccessFault(tval),
+            Exception::LoadPageFault(tval) => Exception::StorePageFault(tval),
+            other => other,
+        }
+    }
+
+    fn is_fetch_fault(&self) -> bool {
+        matches!(self,
+            Exception::InstructionAddressMisaligned(_)
+            | Exception::InstructionAccessFault(_)
+            | Exception::InstructionPageFault(_))
+    }
+}
+
+/// Asynchronous interrupts, numbered by their `xcause` code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware = 1,
+    MachineSoftware = 3,
+    SupervisorTimer = 5,
+    MachineTimer = 7,
+    SupervisorExternal = 9,
+    MachineExternal = 11,
+}
+
+/// Interrupts in the order they are taken when several are pending.
+pub const INTERRUPT_PRIORITY: [Interrupt; 6] = [
+    Interrupt::MachineExternal,
+    Interrupt::MachineSoftware,
+    Interrupt::MachineTimer,
+    Interrupt::SupervisorExternal,
+    Interrupt::SupervisorSoftware,
+    Interrupt::SupervisorTimer,
+];
+
+impl Interrupt {
+    pub fn code(&self) -> u64 {
+        *self as u64
+    }
+
+    /// The bit standing for this interrupt in `mip` and `mie`.
+    pub fn mask(&self) -> u64 {
+        0x1 << self.code()
+    }
+}
+
+/// Trap vector for `cause` given the contents of `xtvec`: in vectored
+/// mode interrupts jump to `BASE + 4 * cause`, exceptions always to `BASE`.
+pub fn trap_vector(tvec: u64, cause: u64, is_interrupt: bool) -> u64 {
+    let base = tvec & !0x3;
+    match tvec & 0x3 {
+        0x1 if is_interrupt => base.wrapping_add(4 * cause),
+        _ => base,
+    }
+}
+
+/// An exception raised while fetching at the very address the trap
+/// handler lives at would keep the hart trapping forever.
+pub fn is_double_fault(exception: &Exception, handler: u64) -> bool {
+    exception.is_fetch_fault() && exception.tval() == handler
+}
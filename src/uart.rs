@@ -0,0 +1,279 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+#[cfg(unix)]
+use std::sync::OnceLock;
+use std::thread;
+
+use crate::device::{Device, Dma};
+use crate::errors::SystemBusError;
+use crate::fdt::DtNode;
+
+pub const UART_BASE: u64 = 0x1000_0000;
+pub const UART_SIZE: u64 = 0x100;
+// PLIC source of the UART on the QEMU virt machine
+pub const UART_IRQ: usize = 10;
+// Input clock of the 16550 on the QEMU virt machine
+const UART_CLOCK_FREQ: u32 = 3_686_400;
+
+pub(crate) const RBR_THR_DLL: u64 = 0;
+const IER_DLM: u64 = 1;
+const IIR_FCR: u64 = 2;
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+pub(crate) const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+const IER_ERBFI: u8 = 0x1 << 0;
+const IER_ETBEI: u8 = 0x1 << 1;
+const IER_MASK: u8 = 0x0f;
+
+const IIR_NO_INT: u8 = 0x01;
+const IIR_THRE: u8 = 0x02;
+const IIR_RDA: u8 = 0x04;
+const IIR_FIFO_ENABLED: u8 = 0xc0;
+
+const FCR_ENABLE: u8 = 0x1 << 0;
+const FCR_CLEAR_RX: u8 = 0x1 << 1;
+
+const LCR_DLAB: u8 = 0x1 << 7;
+
+pub(crate) const LSR_DR: u8 = 0x1 << 0;
+const LSR_THRE: u8 = 0x1 << 5;
+const LSR_TEMT: u8 = 0x1 << 6;
+
+const RX_FIFO_SIZE: usize = 16;
+
+/// Host stdin, byte by byte, read from a background thread.
+pub fn stdin_channel() -> Receiver<u8> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = io::stdin().read(&mut buf) {
+            if n == 0 || buf[..n].iter().any(|&byte| tx.send(byte).is_err()) {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// NS16550A-compatible UART. Transmission is instantaneous, so THR is
+/// always empty; received bytes come from an optional host channel.
+pub struct Uart {
+    rx: VecDeque<u8>,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    dll: u8,
+    dlm: u8,
+    // THR-empty interrupt, raised by a transmit and cleared by an IIR read
+    thre_pending: bool,
+
+    output: Box<dyn Write + Send>,
+    input: Option<Receiver<u8>>,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Uart {
+            rx: VecDeque::new(),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            dll: 0,
+            dlm: 0,
+            thre_pending: false,
+            output: Box::new(io::stdout()),
+            input: None,
+        }
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+        self.output = output;
+    }
+
+    pub fn set_input(&mut self, input: Receiver<u8>) {
+        self.input = Some(input);
+    }
+
+    /// Feeds host stdin to the receiver from a background thread.
+    pub fn attach_stdin(&mut self) {
+        self.set_input(stdin_channel());
+    }
+
+    fn rx_capacity(&self) -> usize {
+        if self.fcr & FCR_ENABLE != 0 { RX_FIFO_SIZE } else { 1 }
+    }
+
+    fn iir(&self) -> u8 {
+        let fifo = if self.fcr & FCR_ENABLE != 0 { IIR_FIFO_ENABLED } else { 0 };
+        let id = if self.ier & IER_ERBFI != 0 && !self.rx.is_empty() {
+            IIR_RDA
+        } else if self.ier & IER_ETBEI != 0 && self.thre_pending {
+            IIR_THRE
+        } else {
+            IIR_NO_INT
+        };
+        fifo | id
+    }
+}
+
+impl Device for Uart {
+    fn load(&mut self, offset: u64, size: usize) -> Result<u64, SystemBusError> {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let value = match offset {
+            RBR_THR_DLL if dlab => self.dll,
+            RBR_THR_DLL => self.rx.pop_front().unwrap_or(0),
+            IER_DLM if dlab => self.dlm,
+            IER_DLM => self.ier,
+            IIR_FCR => {
+                let iir = self.iir();
+                if iir & 0xf == IIR_THRE {
+                    self.thre_pending = false;
+                }
+                iir
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                let dr = if self.rx.is_empty() { 0 } else { LSR_DR };
+                dr | LSR_THRE | LSR_TEMT
+            }
+            MSR => 0,
+            SCR => self.scr,
+            _ => return Err(SystemBusError::InvalidAddress),
+        };
+        Ok(value as u64)
+    }
+
+    fn store(&mut self, data: u64, offset: u64, size: usize) -> Result<(), SystemBusError> {
+        let data = data as u8;
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            RBR_THR_DLL if dlab => self.dll = data,
+            RBR_THR_DLL => {
+                // The console is best effort, a closed stdout is not the guest's problem
+                let _ = self.output.write_all(&[data]).and_then(|_| self.output.flush());
+                self.thre_pending = true;
+            }
+            IER_DLM if dlab => self.dlm = data,
+            IER_DLM => {
+                // Enabling ETBEI while THR is empty raises the interrupt at once
+                if data & IER_ETBEI != 0 && self.ier & IER_ETBEI == 0 {
+                    self.thre_pending = true;
+                }
+                self.ier = data & IER_MASK;
+            }
+            IIR_FCR => {
+                if data & FCR_CLEAR_RX != 0 {
+                    self.rx.clear();
+                }
+                self.fcr = data & (FCR_ENABLE | 0xc0);
+                self.rx.truncate(self.rx_capacity());
+            }
+            LCR => self.lcr = data,
+            MCR => self.mcr = data & 0x1f,
+            LSR | MSR => {}
+            SCR => self.scr = data,
+            _ => return Err(SystemBusError::InvalidAddress),
+        }
+        Ok(())
+    }
+
+    /// Moves pending host input into the receive FIFO.
+    fn tick(&mut self, dma: &mut Dma) {
+        if let Some(input) = &self.input {
+            while self.rx.len() < self.rx_capacity() {
+                match input.try_recv() {
+                    Ok(byte) => self.rx.push_back(byte),
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.iir() & IIR_NO_INT == 0
+    }
+
+    fn dt_node(&self) -> Option<DtNode> {
+        Some(DtNode {
+            name: "serial",
+            compatible: "ns16550a",
+            props: vec![("clock-frequency", UART_CLOCK_FREQ)],
+        })
+    }
+}
+
+impl Default for Uart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Puts the host terminal in raw mode until dropped, so keystrokes reach
+/// the guest one by one and unechoed. Ctrl-C still stops the emulator.
+#[cfg(unix)]
+pub struct RawMode {
+    saved: libc::termios,
+}
+
+// Terminal settings to put back when Ctrl-C kills the emulator
+#[cfg(unix)]
+static SAVED_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn restore_and_exit(_: libc::c_int) {
+    // Only async-signal-safe calls in here
+    unsafe {
+        if let Some(saved) = SAVED_TERMIOS.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+        libc::_exit(130);
+    }
+}
+
+#[cfg(unix)]
+impl RawMode {
+    /// `None` when stdin is not a terminal.
+    pub fn enable() -> Option<RawMode> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return None;
+            }
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            raw.c_lflag |= libc::ISIG;
+            raw.c_oflag |= libc::OPOST | libc::ONLCR;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            let _ = SAVED_TERMIOS.set(saved);
+            let handler: extern "C" fn(libc::c_int) = restore_and_exit;
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+            Some(RawMode { saved })
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
@@ -0,0 +1,314 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::device::Dma;
+use crate::virtio::{Chain, VirtioDevice, Virtqueue};
+
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+
+pub const VIRTIO_BLK_F_RO: u64 = 0x1 << 5;
+pub const VIRTIO_BLK_F_FLUSH: u64 = 0x1 << 9;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+pub const SECTOR_SIZE: u64 = 512;
+const HEADER_SIZE: usize = 16;
+const ID_SIZE: usize = 20;
+
+pub trait Image: Read + Seek {}
+impl<T: Read + Seek> Image for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskMode {
+    // Writes fail and the device is advertised as read-only
+    ReadOnly,
+    // Writes land in memory, the image itself is never modified
+    CopyOnWrite,
+}
+
+/// Disk image backing a virtio-blk device.
+pub struct Disk {
+    image: Box<dyn Image>,
+    sectors: u64,
+    mode: DiskMode,
+    // Sectors written in copy-on-write mode
+    overlay: HashMap<u64, Vec<u8>>,
+}
+
+impl Disk {
+    pub fn new(mut image: Box<dyn Image>, mode: DiskMode) -> io::Result<Self> {
+        let size = image.seek(SeekFrom::End(0))?;
+        Ok(Disk {
+            image,
+            sectors: size / SECTOR_SIZE,
+            mode,
+            overlay: HashMap::new(),
+        })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, mode: DiskMode) -> io::Result<Self> {
+        Disk::new(Box::new(File::open(path)?), mode)
+    }
+
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    fn check_range(&self, sector: u64, len: usize) -> io::Result<()> {
+        let count = len as u64 / SECTOR_SIZE;
+        match sector.checked_add(count) {
+            Some(end) if end <= self.sectors && len as u64 & (SECTOR_SIZE - 1) == 0 => Ok(()),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "request out of range")),
+        }
+    }
+
+    pub fn read(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.check_range(sector, buf.len())?;
+        for (i, chunk) in buf.chunks_mut(SECTOR_SIZE as usize).enumerate() {
+            let sector = sector + i as u64;
+            match self.overlay.get(&sector) {
+                Some(data) => chunk.copy_from_slice(data),
+                None => {
+                    self.image.seek(SeekFrom::Start(sector * SECTOR_SIZE))?;
+                    self.image.read_exact(chunk)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, sector: u64, data: &[u8]) -> io::Result<()> {
+        if self.mode == DiskMode::ReadOnly {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only disk"));
+        }
+        self.check_range(sector, data.len())?;
+        for (i, chunk) in data.chunks(SECTOR_SIZE as usize).enumerate() {
+            self.overlay.insert(sector + i as u64, chunk.to_vec());
+        }
+        Ok(())
+    }
+}
+
+/// virtio-blk device.
+pub struct VirtioBlk {
+    disk: Disk,
+}
+
+impl VirtioBlk {
+    pub fn new(disk: Disk) -> Self {
+        VirtioBlk { disk }
+    }
+
+    // Carries out one request, returning the data for the writable
+    // buffers with the status byte last
+    fn handle(&mut self, request: &[u8], writable: usize) -> Vec<u8> {
+        if request.len() < HEADER_SIZE || writable < 1 {
+            return vec![VIRTIO_BLK_S_IOERR];
+        }
+        let kind = u32::from_le_bytes(request[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(request[8..16].try_into().unwrap());
+        match kind {
+            VIRTIO_BLK_T_IN => {
+                let mut data = vec![0; writable - 1];
+                let status = match self.disk.read(sector, &mut data) {
+                    Ok(()) => VIRTIO_BLK_S_OK,
+                    Err(_) => VIRTIO_BLK_S_IOERR,
+                };
+                data.push(status);
+                data
+            }
+            VIRTIO_BLK_T_OUT => match self.disk.write(sector, &request[HEADER_SIZE..]) {
+                Ok(()) => vec![VIRTIO_BLK_S_OK],
+                Err(_) => vec![VIRTIO_BLK_S_IOERR],
+            },
+            VIRTIO_BLK_T_FLUSH => vec![VIRTIO_BLK_S_OK],
+            VIRTIO_BLK_T_GET_ID => {
+                let mut id = b"j4frv32emu".to_vec();
+                id.resize(ID_SIZE.min(writable - 1), 0);
+                id.push(VIRTIO_BLK_S_OK);
+                id
+            }
+            _ => vec![VIRTIO_BLK_S_UNSUPP],
+        }
+    }
+
+    fn complete(&mut self, chain: &Chain, dma: &mut Dma) -> u32 {
+        let request = match chain.readable(dma) {
+            Ok(request) => request,
+            Err(_) => return 0,
+        };
+        let writable = chain.writable_len();
+        let mut response = self.handle(&request, writable);
+        // The status byte always goes to the very end of the chain
+        if response.len() < writable {
+            let status = response.pop().unwrap();
+            response.resize(writable - 1, 0);
+            response.push(status);
+        }
+        chain.write(dma, &response).unwrap_or(0)
+    }
+}
+
+impl VirtioDevice for VirtioBlk {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_BLOCK
+    }
+
+    fn features(&self) -> u64 {
+        match self.disk.mode {
+            DiskMode::ReadOnly => VIRTIO_BLK_F_FLUSH | VIRTIO_BLK_F_RO,
+            DiskMode::CopyOnWrite => VIRTIO_BLK_F_FLUSH,
+        }
+    }
+
+    fn queue_count(&self) -> usize {
+        1
+    }
+
+    // Only the capacity, in 512-byte sectors
+    fn config(&self) -> Vec<u8> {
+        self.disk.sectors.to_le_bytes().to_vec()
+    }
+
+    fn notify(&mut self, queue: usize, queues: &mut [Virtqueue], dma: &mut Dma) -> bool {
+        let mut used = false;
+        while let Ok(Some(chain)) = queues[queue].pop(dma) {
+            let len = self.complete(&chain, dma);
+            used |= queues[queue].push(dma, chain.head, len).is_ok();
+        }
+        used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::virtio::test_driver::*;
+    use crate::virtio::*;
+
+    use super::*;
+
+    fn make_image() -> Vec<u8> {
+        (0..4 * SECTOR_SIZE).map(|i| (i / SECTOR_SIZE) as u8 + 1).collect()
+    }
+
+    fn request(bus: &mut crate::system_bus::SystemBus, kind: u32, sector: u64, header: u64) {
+        bus.store(kind as u64, header, 32).unwrap();
+        bus.store(sector, header + 8, 64).unwrap();
+    }
+
+    #[test]
+    fn blk_read_write_test() {
+        let mut bus = make_bus();
+        let disk = Disk::new(Box::new(Cursor::new(make_image())), DiskMode::CopyOnWrite).unwrap();
+        let blk = VirtioMmio::new(Box::new(VirtioBlk::new(disk)));
+        bus.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(blk), Some(VIRTIO_IRQ)).unwrap();
+
+        assert_eq!(bus.load(VIRTIO_BASE, 32).unwrap(), 0x7472_6976);
+        assert_eq!(bus.load(VIRTIO_BASE + DEVICE_ID, 32).unwrap(), 2);
+        // Capacity in sectors
+        assert_eq!(bus.load(VIRTIO_BASE + CONFIG, 64).unwrap(), 4);
+
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        let (header, data, status) = (RAM_BASE + 0x2000, RAM_BASE + 0x3000, RAM_BASE + 0x4000);
+
+        // Read sector 2
+        request(&mut bus, 0, 2, header);
+        let head = driver.add(&mut bus, &[(header, 16, false), (data, 512, true), (status, 1, true)]);
+        driver.kick(&mut bus);
+        bus.tick();
+        assert_eq!(driver.used_idx(&mut bus), 1);
+        assert_eq!(driver.used(&mut bus, 0), (head, 513));
+        assert_eq!(bus.load(data + 100, 8).unwrap(), 3);
+        assert_eq!(bus.load(status, 8).unwrap(), 0);
+        assert!(bus.plic.irq_pending_source(VIRTIO_IRQ));
+
+        // Write sector 1 from the same buffer, then read it back
+        request(&mut bus, 1, 1, header);
+        driver.add(&mut bus, &[(header, 16, false), (data, 512, false), (status, 1, true)]);
+        request(&mut bus, 0, 1, header + 0x10);
+        bus.store(0, data + 0x200, 64).unwrap();
+        driver.add(&mut bus, &[(header + 0x10, 16, false), (data + 0x200, 512, true), (status + 1, 1, true)]);
+        driver.kick(&mut bus);
+        bus.tick();
+        assert_eq!(driver.used_idx(&mut bus), 3);
+        assert_eq!(bus.load(data + 0x200, 8).unwrap(), 3);
+        assert_eq!(bus.load(status + 1, 8).unwrap(), 0);
+
+        // Past the end of the disk
+        request(&mut bus, 0, 4, header);
+        driver.add(&mut bus, &[(header, 16, false), (data, 512, true), (status, 1, true)]);
+        driver.kick(&mut bus);
+        bus.tick();
+        assert_eq!(bus.load(status, 8).unwrap(), 1);
+    }
+
+    #[test]
+    fn blk_read_only_test() {
+        let mut bus = make_bus();
+        let image = Cursor::new(make_image());
+        let disk = Disk::new(Box::new(image), DiskMode::ReadOnly).unwrap();
+        let blk = VirtioMmio::new(Box::new(VirtioBlk::new(disk)));
+        bus.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(blk), Some(VIRTIO_IRQ)).unwrap();
+        assert_eq!(bus.load(VIRTIO_BASE + DEVICE_FEATURES, 32).unwrap() & VIRTIO_BLK_F_RO, VIRTIO_BLK_F_RO);
+
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        let (header, data, status) = (RAM_BASE + 0x2000, RAM_BASE + 0x3000, RAM_BASE + 0x4000);
+        request(&mut bus, 1, 0, header);
+        driver.add(&mut bus, &[(header, 16, false), (data, 512, false), (status, 1, true)]);
+        driver.kick(&mut bus);
+        bus.tick();
+        assert_eq!(bus.load(status, 8).unwrap(), 1);
+
+        // Acknowledging drops the interrupt line
+        bus.store(INT_USED_BUFFER as u64, VIRTIO_BASE + INTERRUPT_ACK, 32).unwrap();
+        bus.tick();
+        assert!(!bus.plic.irq_pending_source(VIRTIO_IRQ));
+    }
+
+    #[test]
+    fn queue_reset_test() {
+        let mut bus = make_bus();
+        let disk = Disk::new(Box::new(Cursor::new(make_image())), DiskMode::CopyOnWrite).unwrap();
+        let blk = VirtioMmio::new(Box::new(VirtioBlk::new(disk)));
+        bus.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(blk), Some(VIRTIO_IRQ)).unwrap();
+
+        // QueueNum = 0 keeps the queue from becoming ready
+        bus.store(0, VIRTIO_BASE + QUEUE_SEL, 32).unwrap();
+        bus.store(0, VIRTIO_BASE + QUEUE_NUM, 32).unwrap();
+        bus.store(1, VIRTIO_BASE + QUEUE_READY, 32).unwrap();
+        assert_eq!(bus.load(VIRTIO_BASE + QUEUE_READY, 32).unwrap(), 0);
+
+        // Requests made available before a device reset are never completed
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        let (header, data, status) = (RAM_BASE + 0x2000, RAM_BASE + 0x3000, RAM_BASE + 0x4000);
+        request(&mut bus, 0, 1, header);
+        driver.add(&mut bus, &[(header, 16, false), (data, 512, true), (status, 1, true)]);
+        bus.store(0, VIRTIO_BASE + STATUS, 32).unwrap();
+        driver.kick(&mut bus);
+        bus.tick();
+        assert_eq!(driver.used_idx(&mut bus), 0);
+
+        // Same when the ready queue is shrunk to nothing mid-stream
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        driver.add(&mut bus, &[(header, 16, false), (data, 512, true), (status, 1, true)]);
+        bus.store(0, VIRTIO_BASE + QUEUE_NUM, 32).unwrap();
+        driver.kick(&mut bus);
+        bus.tick();
+        assert_eq!(driver.used_idx(&mut bus), 0);
+        assert!(!bus.plic.irq_pending_source(VIRTIO_IRQ));
+    }
+}
@@ -0,0 +1,185 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::mpsc::Receiver;
+
+use crate::device::Dma;
+use crate::virtio::{VirtioDevice, Virtqueue};
+
+pub const VIRTIO_ID_CONSOLE: u32 = 3;
+
+pub const VIRTIO_CONSOLE_F_SIZE: u64 = 0x1 << 0;
+pub const VIRTIO_CONSOLE_F_EMERG_WRITE: u64 = 0x1 << 2;
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+// cols, rows, max_nr_ports, emerg_wr
+const CONFIG_SIZE: usize = 12;
+const CONFIG_EMERG_WR: u64 = 8;
+
+/// Single-port virtio-console, seen as hvc0 by Linux.
+pub struct VirtioConsole {
+    cols: u16,
+    rows: u16,
+    output: Box<dyn Write + Send>,
+    input: Option<Receiver<u8>>,
+    // Input waiting for a receive buffer
+    pending: VecDeque<u8>,
+}
+
+impl VirtioConsole {
+    pub fn new() -> Self {
+        VirtioConsole {
+            cols: 80,
+            rows: 25,
+            output: Box::new(io::stdout()),
+            input: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+        self.output = output;
+    }
+
+    pub fn set_input(&mut self, input: Receiver<u8>) {
+        self.input = Some(input);
+    }
+
+    pub fn set_size(&mut self, cols: u16, rows: u16) {
+        self.cols = cols;
+        self.rows = rows;
+    }
+
+    fn write_out(&mut self, data: &[u8]) {
+        // Best effort, like the UART
+        let _ = self.output.write_all(data).and_then(|_| self.output.flush());
+    }
+}
+
+impl VirtioDevice for VirtioConsole {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_CONSOLE
+    }
+
+    fn features(&self) -> u64 {
+        VIRTIO_CONSOLE_F_SIZE | VIRTIO_CONSOLE_F_EMERG_WRITE
+    }
+
+    fn queue_count(&self) -> usize {
+        2
+    }
+
+    fn config(&self) -> Vec<u8> {
+        let mut config = Vec::with_capacity(CONFIG_SIZE);
+        config.extend(self.cols.to_le_bytes());
+        config.extend(self.rows.to_le_bytes());
+        config.extend(1u32.to_le_bytes());
+        config.extend(0u32.to_le_bytes());
+        config
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {
+        if offset == CONFIG_EMERG_WR && !data.is_empty() {
+            self.write_out(&data[..1]);
+        }
+    }
+
+    fn notify(&mut self, queue: usize, queues: &mut [Virtqueue], dma: &mut Dma) -> bool {
+        // Receive buffers are filled from poll()
+        if queue != TRANSMITQ {
+            return false;
+        }
+        let mut used = false;
+        while let Ok(Some(chain)) = queues[TRANSMITQ].pop(dma) {
+            if let Ok(data) = chain.readable(dma) {
+                self.write_out(&data);
+            }
+            used |= queues[TRANSMITQ].push(dma, chain.head, 0).is_ok();
+        }
+        used
+    }
+
+    fn poll(&mut self, queues: &mut [Virtqueue], dma: &mut Dma) -> bool {
+        if let Some(input) = &self.input {
+            self.pending.extend(input.try_iter());
+        }
+        let mut used = false;
+        while !self.pending.is_empty() && queues[RECEIVEQ].has_available(dma) {
+            let chain = match queues[RECEIVEQ].pop(dma) {
+                Ok(Some(chain)) => chain,
+                _ => break,
+            };
+            let count = chain.writable_len().min(self.pending.len());
+            let data: Vec<u8> = self.pending.drain(..count).collect();
+            let len = chain.write(dma, &data).unwrap_or(0);
+            used |= queues[RECEIVEQ].push(dma, chain.head, len).is_ok();
+        }
+        used
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+impl Default for VirtioConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use crate::test_util::Capture;
+    use crate::virtio::test_driver::*;
+    use crate::virtio::*;
+
+    use super::*;
+
+    #[test]
+    fn console_test() {
+        let output = Capture::default();
+        let (tx, rx) = mpsc::channel();
+        let mut console = VirtioConsole::new();
+        console.set_output(Box::new(output.clone()));
+        console.set_input(rx);
+
+        let mut bus = make_bus();
+        bus.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(VirtioMmio::new(Box::new(console))),
+                     Some(VIRTIO_IRQ)).unwrap();
+        assert_eq!(bus.load(VIRTIO_BASE + DEVICE_ID, 32).unwrap(), 3);
+        assert_eq!(bus.load(VIRTIO_BASE + CONFIG, 16).unwrap(), 80);
+        let mut rxq = Driver::new(&mut bus, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        let mut txq = Driver::new(&mut bus, VIRTIO_BASE, 1, RAM_BASE + 0x2000);
+
+        bus.store(0x6968, RAM_BASE + 0x3000, 16).unwrap();
+        txq.add(&mut bus, &[(RAM_BASE + 0x3000, 2, false)]);
+        txq.kick(&mut bus);
+        bus.tick();
+        assert_eq!(txq.used_idx(&mut bus), 1);
+        // Emergency writes skip the queues
+        bus.store(b'!' as u64, VIRTIO_BASE + CONFIG + CONFIG_EMERG_WR, 32).unwrap();
+        assert_eq!(output.contents(), b"hi!");
+
+        // Input is held back until there is a buffer, then split across buffers
+        tx.send(b'a').unwrap();
+        tx.send(b'b').unwrap();
+        tx.send(b'c').unwrap();
+        bus.tick();
+        assert_eq!(rxq.used_idx(&mut bus), 0);
+        rxq.add(&mut bus, &[(RAM_BASE + 0x4000, 2, true)]);
+        rxq.add(&mut bus, &[(RAM_BASE + 0x5000, 16, true)]);
+        bus.tick();
+        assert_eq!(rxq.used_idx(&mut bus), 2);
+        assert_eq!(rxq.used(&mut bus, 0).1, 2);
+        assert_eq!(rxq.used(&mut bus, 1).1, 1);
+        assert_eq!(bus.load(RAM_BASE + 0x4000, 16).unwrap(), 0x6261);
+        assert_eq!(bus.load(RAM_BASE + 0x5000, 8).unwrap(), b'c' as u64);
+    }
+}
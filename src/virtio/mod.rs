@@ -0,0 +1,474 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+pub mod blk;
+pub mod console;
+pub mod net;
+pub mod rng;
+
+use crate::device::{Device, Dma};
+use crate::errors::SystemBusError;
+use crate::fdt::DtNode;
+
+// Slots of the QEMU virt machine: 0x1000_1000 + 0x1000 * n, PLIC source 1 + n
+pub const VIRTIO_BASE: u64 = 0x1000_1000;
+pub const VIRTIO_SIZE: u64 = 0x1000;
+pub const VIRTIO_IRQ: usize = 1;
+pub const VIRTIO_SLOTS: usize = 8;
+
+const MAGIC_VALUE: u64 = 0x000;
+const VERSION: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const VENDOR_ID: u64 = 0x00c;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const DRIVER_FEATURES: u64 = 0x020;
+const DRIVER_FEATURES_SEL: u64 = 0x024;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM_MAX: u64 = 0x034;
+const QUEUE_NUM: u64 = 0x038;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC_LOW: u64 = 0x080;
+const QUEUE_DESC_HIGH: u64 = 0x084;
+const QUEUE_DRIVER_LOW: u64 = 0x090;
+const QUEUE_DRIVER_HIGH: u64 = 0x094;
+const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const CONFIG_GENERATION: u64 = 0x0fc;
+const CONFIG: u64 = 0x100;
+
+const MAGIC: u64 = 0x7472_6976;
+// "QEMU", which is what guests expect on the virt layout
+const VENDOR: u64 = 0x554d_4551;
+
+pub const VIRTIO_F_VERSION_1: u64 = 0x1 << 32;
+
+pub const STATUS_DRIVER_OK: u32 = 0x4;
+
+pub const INT_USED_BUFFER: u32 = 0x1;
+pub const INT_CONFIG_CHANGE: u32 = 0x2;
+
+pub const QUEUE_NUM_MAX_VALUE: u16 = 256;
+
+const VIRTQ_DESC_F_NEXT: u16 = 0x1;
+const VIRTQ_DESC_F_WRITE: u16 = 0x2;
+
+/// One buffer of a descriptor chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub write: bool,
+}
+
+/// A descriptor chain taken from the available ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain {
+    pub head: u16,
+    pub descs: Vec<Descriptor>,
+}
+
+impl Chain {
+    /// Contents of the device-readable buffers, concatenated.
+    pub fn readable(&self, dma: &Dma) -> Result<Vec<u8>, SystemBusError> {
+        let mut data = Vec::new();
+        for desc in self.descs.iter().filter(|desc| !desc.write) {
+            let start = data.len();
+            data.resize(start + desc.len as usize, 0);
+            dma.read(desc.addr, &mut data[start..])?;
+        }
+        Ok(data)
+    }
+
+    pub fn writable_len(&self) -> usize {
+        self.descs.iter().filter(|desc| desc.write).map(|desc| desc.len as usize).sum()
+    }
+
+    /// Fills the device-writable buffers in order with `data`, returning
+    /// how many bytes fit.
+    pub fn write(&self, dma: &mut Dma, data: &[u8]) -> Result<u32, SystemBusError> {
+        let mut written = 0;
+        for desc in self.descs.iter().filter(|desc| desc.write) {
+            if written == data.len() {
+                break;
+            }
+            let n = (desc.len as usize).min(data.len() - written);
+            dma.write(desc.addr, &data[written..written + n])?;
+            written += n;
+        }
+        Ok(written as u32)
+    }
+}
+
+/// Split virtqueue as laid out by the driver in guest memory.
+#[derive(Debug, Default)]
+pub struct Virtqueue {
+    pub num: u16,
+    pub ready: bool,
+    pub desc: u64,
+    pub driver: u64,
+    pub device: u64,
+    last_avail_idx: u16,
+    used_idx: u16,
+}
+
+impl Virtqueue {
+    /// Whether the driver has made a chain available, without taking it.
+    pub fn has_available(&self, dma: &Dma) -> bool {
+        self.ready && self.num != 0
+            && dma.load(self.driver + 2, 16).is_ok_and(|idx| idx as u16 != self.last_avail_idx)
+    }
+
+    /// Next chain the driver made available, if any.
+    pub fn pop(&mut self, dma: &Dma) -> Result<Option<Chain>, SystemBusError> {
+        if !self.ready || self.num == 0 {
+            return Ok(None);
+        }
+        let avail_idx = dma.load(self.driver + 2, 16)? as u16;
+        if avail_idx == self.last_avail_idx {
+            return Ok(None);
+        }
+        let slot = (self.last_avail_idx % self.num) as u64;
+        let head = dma.load(self.driver + 4 + 2 * slot, 16)? as u16;
+        self.last_avail_idx = self.last_avail_idx.wrapping_add(1);
+
+        let mut descs = Vec::new();
+        let mut index = head;
+        // A well-formed chain never visits more descriptors than the queue holds
+        for _ in 0..self.num {
+            if index >= self.num {
+                return Err(SystemBusError::InvalidAddress);
+            }
+            let entry = self.desc + 16 * index as u64;
+            let flags = dma.load(entry + 12, 16)? as u16;
+            descs.push(Descriptor {
+                addr: dma.load(entry, 64)?,
+                len: dma.load(entry + 8, 32)? as u32,
+                write: flags & VIRTQ_DESC_F_WRITE != 0,
+            });
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(Some(Chain { head, descs }));
+            }
+            index = dma.load(entry + 14, 16)? as u16;
+        }
+        Err(SystemBusError::InvalidAddress)
+    }
+
+    /// Returns the chain at `head` to the driver with `len` bytes written.
+    pub fn push(&mut self, dma: &mut Dma, head: u16, len: u32) -> Result<(), SystemBusError> {
+        // The driver may have reset the queue since the chain was popped
+        if !self.ready || self.num == 0 {
+            return Ok(());
+        }
+        let slot = (self.used_idx % self.num) as u64;
+        let elem = self.device + 4 + 8 * slot;
+        dma.store(head as u64, elem, 32)?;
+        dma.store(len as u64, elem + 4, 32)?;
+        self.used_idx = self.used_idx.wrapping_add(1);
+        dma.store(self.used_idx as u64, self.device + 2, 16)
+    }
+}
+
+/// Device side of a virtio device, independent of the transport.
+pub trait VirtioDevice {
+    fn device_id(&self) -> u32;
+
+    /// Device-specific feature bits; the transport adds VIRTIO_F_VERSION_1.
+    fn features(&self) -> u64 {
+        0
+    }
+
+    fn queue_count(&self) -> usize;
+
+    /// Device configuration space.
+    fn config(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) {}
+
+    /// The driver made buffers available on `queue`. Returns whether any
+    /// buffer was used, which interrupts the driver.
+    fn notify(&mut self, queue: usize, queues: &mut [Virtqueue], dma: &mut Dma) -> bool;
+
+    /// Host-side work done every cycle once the driver is up, like
+    /// delivering received data. Returns whether any buffer was used.
+    fn poll(&mut self, queues: &mut [Virtqueue], dma: &mut Dma) -> bool {
+        false
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// virtio-mmio (version 2) transport around a `VirtioDevice`.
+pub struct VirtioMmio {
+    device: Box<dyn VirtioDevice>,
+    queues: Vec<Virtqueue>,
+    queue_sel: u32,
+    device_features_sel: u32,
+    driver_features_sel: u32,
+    driver_features: u64,
+    status: u32,
+    interrupt_status: u32,
+    // Queues notified since the last tick
+    notified: u64,
+}
+
+impl VirtioMmio {
+    pub fn new(device: Box<dyn VirtioDevice>) -> Self {
+        let queues = (0..device.queue_count()).map(|_| Virtqueue::default()).collect();
+        VirtioMmio {
+            device,
+            queues,
+            queue_sel: 0,
+            device_features_sel: 0,
+            driver_features_sel: 0,
+            driver_features: 0,
+            status: 0,
+            interrupt_status: 0,
+            notified: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.queues.iter_mut().for_each(|queue| *queue = Virtqueue::default());
+        self.queue_sel = 0;
+        self.device_features_sel = 0;
+        self.driver_features_sel = 0;
+        self.driver_features = 0;
+        self.status = 0;
+        self.interrupt_status = 0;
+        self.notified = 0;
+        self.device.reset();
+    }
+
+    pub fn driver_features(&self) -> u64 {
+        self.driver_features
+    }
+
+    fn features(&self) -> u64 {
+        self.device.features() | VIRTIO_F_VERSION_1
+    }
+
+    fn queue(&mut self) -> Option<&mut Virtqueue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    fn set_half(value: &mut u64, data: u64, high: bool) {
+        *value = if high {
+            (*value & 0xffff_ffff) | (data << 32)
+        } else {
+            (*value & !0xffff_ffff) | (data & 0xffff_ffff)
+        };
+    }
+}
+
+impl Device for VirtioMmio {
+    fn load(&mut self, offset: u64, size: usize) -> Result<u64, SystemBusError> {
+        if offset >= CONFIG {
+            let config = self.device.config();
+            let start = (offset - CONFIG) as usize;
+            let bytes = config.get(start..start + size / 8).ok_or(SystemBusError::InvalidAddress)?;
+            return Ok(bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | byte as u64));
+        }
+        // Registers are 32 bits wide
+        if size != 32 {
+            return Err(SystemBusError::InvalidAddress);
+        }
+        let value = match offset {
+            MAGIC_VALUE => MAGIC,
+            VERSION => 2,
+            DEVICE_ID => self.device.device_id() as u64,
+            VENDOR_ID => VENDOR,
+            DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.features() & 0xffff_ffff,
+                1 => self.features() >> 32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX => self.queue().map_or(0, |_| QUEUE_NUM_MAX_VALUE as u64),
+            QUEUE_READY => self.queue().map_or(0, |queue| queue.ready as u64),
+            INTERRUPT_STATUS => self.interrupt_status as u64,
+            STATUS => self.status as u64,
+            CONFIG_GENERATION => 0,
+            _ => 0,
+        };
+        Ok(value)
+    }
+
+    fn store(&mut self, data: u64, offset: u64, size: usize) -> Result<(), SystemBusError> {
+        if offset >= CONFIG {
+            let bytes = data.to_le_bytes();
+            self.device.write_config(offset - CONFIG, &bytes[..size / 8]);
+            return Ok(());
+        }
+        if size != 32 {
+            return Err(SystemBusError::InvalidAddress);
+        }
+        let data = data & 0xffff_ffff;
+        match offset {
+            DEVICE_FEATURES_SEL => self.device_features_sel = data as u32,
+            // The driver can only accept what was offered
+            DRIVER_FEATURES if self.driver_features_sel < 2 => {
+                let offered = self.features();
+                Self::set_half(&mut self.driver_features, data, self.driver_features_sel == 1);
+                self.driver_features &= offered;
+            }
+            DRIVER_FEATURES_SEL => self.driver_features_sel = data as u32,
+            QUEUE_SEL => self.queue_sel = data as u32,
+            QUEUE_NUM => {
+                if let Some(queue) = self.queue() {
+                    queue.num = (data as u16).min(QUEUE_NUM_MAX_VALUE);
+                }
+            }
+            QUEUE_READY => {
+                if let Some(queue) = self.queue() {
+                    // A queue without entries cannot be made ready
+                    queue.ready = data & 0x1 != 0 && queue.num != 0;
+                }
+            }
+            QUEUE_NOTIFY if (data as usize) < self.queues.len() => self.notified |= 0x1 << data,
+            INTERRUPT_ACK => self.interrupt_status &= !(data as u32),
+            STATUS => {
+                if data == 0 {
+                    self.reset();
+                } else {
+                    self.status = data as u32;
+                }
+            }
+            QUEUE_DESC_LOW | QUEUE_DESC_HIGH => {
+                if let Some(queue) = self.queue() {
+                    Self::set_half(&mut queue.desc, data, offset == QUEUE_DESC_HIGH);
+                }
+            }
+            QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH => {
+                if let Some(queue) = self.queue() {
+                    Self::set_half(&mut queue.driver, data, offset == QUEUE_DRIVER_HIGH);
+                }
+            }
+            QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH => {
+                if let Some(queue) = self.queue() {
+                    Self::set_half(&mut queue.device, data, offset == QUEUE_DEVICE_HIGH);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, dma: &mut Dma) {
+        let mut used = false;
+        while self.notified != 0 {
+            let queue = self.notified.trailing_zeros() as usize;
+            self.notified &= self.notified - 1;
+            used |= self.device.notify(queue, &mut self.queues, dma);
+        }
+        if self.status & STATUS_DRIVER_OK != 0 {
+            used |= self.device.poll(&mut self.queues, dma);
+        }
+        if used {
+            self.interrupt_status |= INT_USED_BUFFER;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.interrupt_status != 0
+    }
+
+    fn dt_node(&self) -> Option<DtNode> {
+        Some(DtNode { name: "virtio_mmio", compatible: "virtio,mmio", props: Vec::new() })
+    }
+}
+
+/// Minimal driver side of a split virtqueue, for the device tests.
+#[cfg(test)]
+pub(crate) mod test_driver {
+    use crate::system_bus::{SystemBus, SystemBusMap};
+
+    use super::*;
+
+    pub const RAM_BASE: u64 = 0x8000_0000;
+
+    pub fn make_bus() -> SystemBus {
+        SystemBus::new(SystemBusMap {
+            dram_base_addr: RAM_BASE,
+            dram_size: 0x1_0000,
+        })
+    }
+
+    pub struct Driver {
+        pub base: u64,
+        pub queue: u32,
+        desc: u64,
+        avail: u64,
+        used: u64,
+        num: u16,
+        next_desc: u16,
+        avail_idx: u16,
+    }
+
+    impl Driver {
+        /// Sets up queue `queue` of the device at `base` with its rings at `ring`.
+        pub fn new(bus: &mut SystemBus, base: u64, queue: u32, ring: u64) -> Driver {
+            let num = 8;
+            let driver = Driver {
+                base,
+                queue,
+                desc: ring,
+                avail: ring + 0x100,
+                used: ring + 0x200,
+                num,
+                next_desc: 0,
+                avail_idx: 0,
+            };
+            bus.store(queue as u64, base + QUEUE_SEL, 32).unwrap();
+            bus.store(num as u64, base + QUEUE_NUM, 32).unwrap();
+            bus.store(driver.desc, base + QUEUE_DESC_LOW, 32).unwrap();
+            bus.store(driver.avail, base + QUEUE_DRIVER_LOW, 32).unwrap();
+            bus.store(driver.used, base + QUEUE_DEVICE_LOW, 32).unwrap();
+            bus.store(1, base + QUEUE_READY, 32).unwrap();
+            bus.store(STATUS_DRIVER_OK as u64, base + STATUS, 32).unwrap();
+            driver
+        }
+
+        /// Makes a chain of (addr, len, device-writable) buffers available.
+        pub fn add(&mut self, bus: &mut SystemBus, bufs: &[(u64, u32, bool)]) -> u16 {
+            let head = self.next_desc % self.num;
+            for (i, &(addr, len, write)) in bufs.iter().enumerate() {
+                let index = (self.next_desc % self.num) as u64;
+                let entry = self.desc + 16 * index;
+                let mut flags = if write { VIRTQ_DESC_F_WRITE } else { 0 };
+                if i + 1 < bufs.len() {
+                    flags |= VIRTQ_DESC_F_NEXT;
+                }
+                bus.store(addr, entry, 64).unwrap();
+                bus.store(len as u64, entry + 8, 32).unwrap();
+                bus.store(flags as u64, entry + 12, 16).unwrap();
+                bus.store((index + 1) % self.num as u64, entry + 14, 16).unwrap();
+                self.next_desc = self.next_desc.wrapping_add(1);
+            }
+            let slot = (self.avail_idx % self.num) as u64;
+            bus.store(head as u64, self.avail + 4 + 2 * slot, 16).unwrap();
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            bus.store(self.avail_idx as u64, self.avail + 2, 16).unwrap();
+            head
+        }
+
+        pub fn kick(&self, bus: &mut SystemBus) {
+            bus.store(self.queue as u64, self.base + QUEUE_NOTIFY, 32).unwrap();
+        }
+
+        pub fn used_idx(&self, bus: &mut SystemBus) -> u16 {
+            bus.load(self.used + 2, 16).unwrap() as u16
+        }
+
+        /// (head, len) of used element `i`.
+        pub fn used(&self, bus: &mut SystemBus, i: u16) -> (u16, u32) {
+            let elem = self.used + 4 + 8 * (i % self.num) as u64;
+            (bus.load(elem, 32).unwrap() as u16, bus.load(elem + 4, 32).unwrap() as u32)
+        }
+    }
+}
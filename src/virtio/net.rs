@@ -0,0 +1,304 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::device::Dma;
+use crate::virtio::{VirtioDevice, Virtqueue};
+
+pub const VIRTIO_ID_NET: u32 = 1;
+
+pub const VIRTIO_NET_F_MAC: u64 = 0x1 << 5;
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+// struct virtio_net_hdr including num_buffers, as used with VERSION_1
+const NET_HDR_SIZE: usize = 12;
+
+pub const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+/// Where frames sent by the guest go and frames for the guest come from.
+pub trait NetBackend {
+    fn send(&mut self, frame: &[u8]);
+
+    /// Next frame for the guest, without blocking.
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NS: u32 = 0xa1b2_3c4d;
+const PCAP_LINKTYPE_ETHERNET: u32 = 1;
+const PCAP_SNAPLEN: u32 = 65535;
+
+/// Offline backend: guest frames are appended to a pcap capture, and
+/// frames of another capture are replayed to the guest.
+pub struct PcapBackend {
+    input: Option<Box<dyn Read>>,
+    // Byte order of the input capture
+    swapped: bool,
+    output: Option<Box<dyn Write>>,
+}
+
+impl PcapBackend {
+    pub fn new(input: Option<Box<dyn Read>>, output: Option<Box<dyn Write>>) -> io::Result<Self> {
+        let mut backend = PcapBackend { input, swapped: false, output };
+        if let Some(input) = backend.input.as_mut() {
+            let mut header = [0; 24];
+            input.read_exact(&mut header)?;
+            let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            backend.swapped = match magic {
+                PCAP_MAGIC | PCAP_MAGIC_NS => false,
+                _ if magic.swap_bytes() == PCAP_MAGIC || magic.swap_bytes() == PCAP_MAGIC_NS => true,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap file")),
+            };
+        }
+        if let Some(output) = backend.output.as_mut() {
+            let mut header = Vec::with_capacity(24);
+            header.extend(PCAP_MAGIC.to_le_bytes());
+            header.extend(2u16.to_le_bytes());
+            header.extend(4u16.to_le_bytes());
+            header.extend(0i32.to_le_bytes());
+            header.extend(0u32.to_le_bytes());
+            header.extend(PCAP_SNAPLEN.to_le_bytes());
+            header.extend(PCAP_LINKTYPE_ETHERNET.to_le_bytes());
+            output.write_all(&header)?;
+        }
+        Ok(backend)
+    }
+
+    fn field(&self, bytes: &[u8]) -> u32 {
+        let value = u32::from_le_bytes(bytes.try_into().unwrap());
+        if self.swapped { value.swap_bytes() } else { value }
+    }
+}
+
+impl NetBackend for PcapBackend {
+    fn send(&mut self, frame: &[u8]) {
+        let output = match self.output.as_mut() {
+            Some(output) => output,
+            None => return,
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut record = Vec::with_capacity(16 + frame.len());
+        record.extend((now.as_secs() as u32).to_le_bytes());
+        record.extend(now.subsec_micros().to_le_bytes());
+        record.extend((frame.len() as u32).to_le_bytes());
+        record.extend((frame.len() as u32).to_le_bytes());
+        record.extend(frame);
+        // A failing capture must not take the guest down with it
+        if output.write_all(&record).and_then(|_| output.flush()).is_err() {
+            self.output = None;
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        let mut header = [0; 16];
+        self.input.as_mut()?.read_exact(&mut header).ok()?;
+        let len = self.field(&header[8..12]);
+        // A record longer than any snaplen means the capture is corrupt
+        if len > PCAP_SNAPLEN {
+            self.input = None;
+            return None;
+        }
+        let mut frame = vec![0; len as usize];
+        if self.input.as_mut()?.read_exact(&mut frame).is_err() {
+            self.input = None;
+            return None;
+        }
+        Some(frame)
+    }
+}
+
+/// One end of an in-process link, so two emulated machines can talk to
+/// each other. Frames sent on one end are received on the other.
+pub struct PacketPipe {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl PacketPipe {
+    pub fn pair() -> (PacketPipe, PacketPipe) {
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+        (PacketPipe { tx: a_tx, rx: a_rx }, PacketPipe { tx: b_tx, rx: b_rx })
+    }
+}
+
+impl NetBackend for PacketPipe {
+    fn send(&mut self, frame: &[u8]) {
+        // Nobody listening on the other end is like an unplugged cable
+        let _ = self.tx.send(frame.to_vec());
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// virtio-net device.
+pub struct VirtioNet {
+    mac: [u8; 6],
+    backend: Box<dyn NetBackend>,
+}
+
+impl VirtioNet {
+    pub fn new(mac: [u8; 6], backend: Box<dyn NetBackend>) -> Self {
+        VirtioNet { mac, backend }
+    }
+}
+
+impl VirtioDevice for VirtioNet {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_NET
+    }
+
+    fn features(&self) -> u64 {
+        VIRTIO_NET_F_MAC
+    }
+
+    fn queue_count(&self) -> usize {
+        2
+    }
+
+    fn config(&self) -> Vec<u8> {
+        self.mac.to_vec()
+    }
+
+    fn notify(&mut self, queue: usize, queues: &mut [Virtqueue], dma: &mut Dma) -> bool {
+        // Receive buffers are filled from poll()
+        if queue != TRANSMITQ {
+            return false;
+        }
+        let mut used = false;
+        while let Ok(Some(chain)) = queues[TRANSMITQ].pop(dma) {
+            if let Ok(packet) = chain.readable(dma) {
+                if packet.len() > NET_HDR_SIZE {
+                    self.backend.send(&packet[NET_HDR_SIZE..]);
+                }
+            }
+            used |= queues[TRANSMITQ].push(dma, chain.head, 0).is_ok();
+        }
+        used
+    }
+
+    fn poll(&mut self, queues: &mut [Virtqueue], dma: &mut Dma) -> bool {
+        let mut used = false;
+        while queues[RECEIVEQ].has_available(dma) {
+            let frame = match self.backend.recv() {
+                Some(frame) => frame,
+                None => break,
+            };
+            let chain = match queues[RECEIVEQ].pop(dma) {
+                Ok(Some(chain)) => chain,
+                _ => break,
+            };
+            // No offloads, a single buffer per packet
+            let mut packet = vec![0; NET_HDR_SIZE];
+            packet[10] = 1;
+            packet.extend(frame);
+            let len = chain.write(dma, &packet).unwrap_or(0);
+            used |= queues[RECEIVEQ].push(dma, chain.head, len).is_ok();
+        }
+        used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::test_util::Capture;
+    use crate::virtio::test_driver::*;
+    use crate::virtio::*;
+
+    use super::*;
+
+    #[test]
+    fn net_pipe_test() {
+        let (a, b) = PacketPipe::pair();
+        let mut bus_a = make_bus();
+        let mut bus_b = make_bus();
+        let net_a = VirtioMmio::new(Box::new(VirtioNet::new(DEFAULT_MAC, Box::new(a))));
+        let net_b = VirtioMmio::new(Box::new(VirtioNet::new(DEFAULT_MAC, Box::new(b))));
+        bus_a.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(net_a), Some(VIRTIO_IRQ)).unwrap();
+        bus_b.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(net_b), Some(VIRTIO_IRQ)).unwrap();
+        assert_eq!(bus_a.load(VIRTIO_BASE + CONFIG + 5, 8).unwrap(), 0x56);
+
+        let rx_b = Driver::new(&mut bus_b, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        let mut tx_a = Driver::new(&mut bus_a, VIRTIO_BASE, 1, RAM_BASE + 0x2000);
+        let mut rx_b = rx_b;
+
+        // A sends a 4-byte frame after a zeroed header
+        let packet = RAM_BASE + 0x3000;
+        bus_a.store(0xdead_beef, packet + NET_HDR_SIZE as u64, 32).unwrap();
+        tx_a.add(&mut bus_a, &[(packet, NET_HDR_SIZE as u32 + 4, false)]);
+        tx_a.kick(&mut bus_a);
+        bus_a.tick();
+        assert_eq!(tx_a.used_idx(&mut bus_a), 1);
+
+        // Nothing is received before B posts a buffer
+        bus_b.tick();
+        assert_eq!(rx_b.used_idx(&mut bus_b), 0);
+        let buffer = RAM_BASE + 0x4000;
+        rx_b.add(&mut bus_b, &[(buffer, 1514, true)]);
+        bus_b.tick();
+        assert_eq!(rx_b.used(&mut bus_b, 0), (0, NET_HDR_SIZE as u32 + 4));
+        assert_eq!(bus_b.load(buffer + NET_HDR_SIZE as u64, 32).unwrap(), 0xdead_beef);
+        assert!(bus_b.plic.irq_pending_source(VIRTIO_IRQ));
+    }
+
+    #[test]
+    fn net_pcap_test() {
+        let capture = Capture::default();
+        // Replay the capture of a single 2-byte frame
+        let mut input = Vec::new();
+        input.extend(PCAP_MAGIC.to_le_bytes());
+        input.extend([0; 20]);
+        input.extend([0; 8]);
+        input.extend(2u32.to_le_bytes());
+        input.extend(2u32.to_le_bytes());
+        input.extend([0xab, 0xcd]);
+        let backend = PcapBackend::new(Some(Box::new(Cursor::new(input))),
+                                       Some(Box::new(capture.clone()))).unwrap();
+
+        let mut bus = make_bus();
+        let net = VirtioMmio::new(Box::new(VirtioNet::new(DEFAULT_MAC, Box::new(backend))));
+        bus.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(net), Some(VIRTIO_IRQ)).unwrap();
+        let mut rx = Driver::new(&mut bus, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        let mut tx = Driver::new(&mut bus, VIRTIO_BASE, 1, RAM_BASE + 0x2000);
+
+        rx.add(&mut bus, &[(RAM_BASE + 0x4000, 1514, true)]);
+        rx.add(&mut bus, &[(RAM_BASE + 0x5000, 1514, true)]);
+        bus.store(0x1122, RAM_BASE + 0x3000 + NET_HDR_SIZE as u64, 16).unwrap();
+        tx.add(&mut bus, &[(RAM_BASE + 0x3000, NET_HDR_SIZE as u32 + 2, false)]);
+        tx.kick(&mut bus);
+        bus.tick();
+
+        assert_eq!(rx.used_idx(&mut bus), 1);
+        assert_eq!(bus.load(RAM_BASE + 0x4000 + NET_HDR_SIZE as u64, 16).unwrap(), 0xcdab);
+        let capture = capture.contents();
+        assert_eq!(capture.len(), 24 + 16 + 2);
+        assert_eq!(&capture[0..4], &PCAP_MAGIC.to_le_bytes());
+        assert_eq!(&capture[40..], &[0x22, 0x11]);
+    }
+    #[test]
+    fn net_pcap_corrupt_test() {
+        // A record claiming 4 GiB ends the replay, even with a frame after it
+        let mut input = Vec::new();
+        input.extend(PCAP_MAGIC.to_le_bytes());
+        input.extend([0; 20]);
+        input.extend([0; 8]);
+        input.extend(u32::MAX.to_le_bytes());
+        input.extend(u32::MAX.to_le_bytes());
+        input.extend([0; 8]);
+        input.extend(2u32.to_le_bytes());
+        input.extend(2u32.to_le_bytes());
+        input.extend([0xab, 0xcd]);
+        let mut backend = PcapBackend::new(Some(Box::new(Cursor::new(input))), None).unwrap();
+        assert_eq!(backend.recv(), None);
+        assert_eq!(backend.recv(), None);
+    }
+}
@@ -0,0 +1,102 @@
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::device::Dma;
+use crate::virtio::{VirtioDevice, Virtqueue};
+
+pub const VIRTIO_ID_RNG: u32 = 4;
+
+/// virtio-rng device. The bytes come from a seeded SplitMix64 generator,
+/// so a run can be replayed exactly by reusing the seed.
+pub struct VirtioRng {
+    seed: u64,
+    state: u64,
+}
+
+impl VirtioRng {
+    pub fn new(seed: u64) -> Self {
+        VirtioRng { seed, state: seed }
+    }
+
+    /// Seeded from the host, see `seed()` to replay the run later.
+    pub fn from_entropy() -> Self {
+        VirtioRng::new(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+impl VirtioDevice for VirtioRng {
+    fn device_id(&self) -> u32 {
+        VIRTIO_ID_RNG
+    }
+
+    fn queue_count(&self) -> usize {
+        1
+    }
+
+    fn config(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn notify(&mut self, queue: usize, queues: &mut [Virtqueue], dma: &mut Dma) -> bool {
+        let mut used = false;
+        while let Ok(Some(chain)) = queues[queue].pop(dma) {
+            let mut data = vec![0; chain.writable_len()];
+            self.fill(&mut data);
+            let len = chain.write(dma, &data).unwrap_or(0);
+            used |= queues[queue].push(dma, chain.head, len).is_ok();
+        }
+        used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::virtio::test_driver::*;
+    use crate::virtio::*;
+
+    use super::*;
+
+    fn entropy(seed: u64) -> (u64, u64) {
+        let mut bus = make_bus();
+        bus.register(VIRTIO_BASE, VIRTIO_SIZE, Box::new(VirtioMmio::new(Box::new(VirtioRng::new(seed)))),
+                     Some(VIRTIO_IRQ)).unwrap();
+        let mut driver = Driver::new(&mut bus, VIRTIO_BASE, 0, RAM_BASE + 0x1000);
+        driver.add(&mut bus, &[(RAM_BASE + 0x2000, 12, true)]);
+        driver.kick(&mut bus);
+        bus.tick();
+        assert_eq!(driver.used(&mut bus, 0).1, 12);
+        (bus.load(RAM_BASE + 0x2000, 64).unwrap(), bus.load(RAM_BASE + 0x2008, 64).unwrap())
+    }
+
+    #[test]
+    fn rng_seed_test() {
+        let (first, tail) = entropy(42);
+        assert_ne!(first, 0);
+        // Only the requested 12 bytes are written
+        assert_eq!(tail >> 32, 0);
+        assert_eq!(entropy(42), (first, tail));
+        assert_ne!(entropy(43).0, first);
+    }
+}